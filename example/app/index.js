@@ -1,3 +1,7 @@
-import { run } from "example";
+import { run, bench } from "example";
 
-run()
+if (window.location.search.includes("bench")) {
+    bench()
+} else {
+    run()
+}
@@ -0,0 +1,87 @@
+//! Compares applying patches one call at a time (Dom) with applying
+//! them in one call per update (Batched). Open the app with `?bench`.
+
+use std::rc::Rc;
+
+use lollipop::{
+    apply_patches, console_log, diff, div, draw, p, set_backend, Backend, Batched, Dom, Node, Patch,
+};
+use wasm_bindgen::prelude::*;
+
+const ROWS: usize = 5000;
+const UPDATES: usize = 10;
+
+#[wasm_bindgen]
+pub fn bench() {
+    measure("per call", Rc::new(Dom::new()));
+    measure("batched", Rc::new(Batched::new()));
+}
+
+fn measure(name: &str, backend: Rc<dyn Backend>) {
+    set_backend(backend.clone());
+    let start = js_sys::Date::now();
+
+    let mut old = table(0);
+    apply_patches(draw("bench", &mut old));
+    old.attach_borrow(backend.root());
+    let drawn = js_sys::Date::now();
+
+    // every update is a frame of its own
+    for generation in 1..=UPDATES {
+        let mut new = table(generation);
+        apply_patches(diff("bench", &mut old, &mut new));
+        backend.flush();
+        old = new;
+    }
+    let updated = js_sys::Date::now();
+
+    // the table leaves for good, so its nodes are forgotten as well
+    let mut patches = vec![Patch::Remove {
+        id: old.dom_node().unwrap(),
+    }];
+    release(&old, &mut patches);
+    apply_patches(patches);
+    backend.flush();
+
+    console_log!(
+        "[bench] {}: draw {} rows in {}ms, {} updates in {}ms",
+        name,
+        ROWS,
+        drawn - start,
+        UPDATES,
+        updated - drawn
+    );
+}
+
+fn release(node: &Node<()>, patches: &mut Vec<Patch>) {
+    match node {
+        Node::Element(el) => {
+            for child in el.children.iter() {
+                release(child, patches);
+            }
+            patches.push(Patch::Release {
+                id: el.dom_element.unwrap(),
+            });
+        }
+        Node::Text(txt) => patches.push(Patch::Release {
+            id: txt.dom_text_node.unwrap(),
+        }),
+        Node::Widget(_) => (),
+    }
+}
+
+fn table(generation: usize) -> Node<()> {
+    let mut table = div();
+    for row in 0..ROWS {
+        let class = if (row + generation).is_multiple_of(2) {
+            "even"
+        } else {
+            "odd"
+        };
+        table = table.child(
+            p().attr("class", class)
+                .child(format!("row {} of generation {}", row, generation)),
+        );
+    }
+    table.node()
+}
@@ -1,8 +1,9 @@
 
 use wasm_bindgen::prelude::*;
 mod app;
+mod bench;
 
 #[wasm_bindgen]
 pub fn run() {
-    lollipop::run::<app::Msg>(app::app())
+    lollipop::run(app::app);
 }
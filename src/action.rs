@@ -1,9 +1,217 @@
-use std::{future::Future, pin::Pin};
+use std::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+
+use crate::runtime::Runtime;
 
 pub enum Action<M> {
     Diff,
     Nothing,
     Async(Pin<Box<dyn Future<Output = M>>>),
+    /// Sends every item as a message as soon as it arrives, until the
+    /// stream ends or the widget unmounts.
+    Stream(Pin<Box<dyn Stream<Item = M>>>),
+    /// Runs all of these at once, rendering at most once.
+    Batch(Vec<Action<M>>),
+    /// Runs these one after the other. Every action starts once the
+    /// futures of the one before have resolved and their messages have
+    /// been handled.
+    Sequence(Vec<Action<M>>),
+    /// Runs the action with its futures and streams under `key`, aborting
+    /// the ones the widget still runs under the same key before they
+    /// start. Inside of a sequence, the futures are left to the sequence
+    /// itself.
+    Keyed {
+        key: String,
+        action: Box<Action<M>>,
+    },
+    /// Aborts the futures and streams running under a key.
+    Cancel(String),
+}
+
+pub(crate) type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// What an action comes down to right away: whether the widget renders,
+/// the keys to cancel and the tasks that send messages to it later.
+#[derive(Default)]
+pub(crate) struct Effects {
+    pub render: bool,
+    pub cancel: Vec<String>,
+    pub tasks: Vec<(Option<String>, Task)>,
+}
+
+impl<M: 'static> Action<M> {
+    pub fn stream(stream: impl Stream<Item = M> + 'static) -> Self {
+        Action::Stream(Box::pin(stream))
+    }
+
+    pub fn batch(actions: impl IntoIterator<Item = Action<M>>) -> Self {
+        Action::Batch(actions.into_iter().collect())
+    }
+
+    pub fn sequence(actions: impl IntoIterator<Item = Action<M>>) -> Self {
+        Action::Sequence(actions.into_iter().collect())
+    }
+
+    /// Runs this action under `key`, so that a newer one under the same
+    /// key aborts it, like a search that is still waiting for results.
+    pub fn key(self, key: impl ToString) -> Self {
+        Action::Keyed {
+            key: key.to_string(),
+            action: Box::new(self),
+        }
+    }
+
+    pub fn cancel(key: impl ToString) -> Self {
+        Action::Cancel(key.to_string())
+    }
+
+    /// Runs `other` as well, like `Action::Diff.and(fetch())` renders
+    /// and starts a request.
+    pub fn and(self, other: impl Into<Action<M>>) -> Self {
+        match self {
+            Action::Batch(mut actions) => {
+                actions.push(other.into());
+                Action::Batch(actions)
+            }
+            action => Action::Batch(vec![action, other.into()]),
+        }
+    }
+
+    /// Runs `next` once this action is done.
+    pub fn then(self, next: impl Into<Action<M>>) -> Self {
+        match self {
+            Action::Sequence(mut actions) => {
+                actions.push(next.into());
+                Action::Sequence(actions)
+            }
+            action => Action::Sequence(vec![action, next.into()]),
+        }
+    }
+
+    /// Turns the messages of this action into those of another widget,
+    /// like the ones of a helper into the ones of the widget using it.
+    pub fn map<N: 'static>(self, f: fn(M) -> N) -> Action<N> {
+        match self {
+            Action::Diff => Action::Diff,
+            Action::Nothing => Action::Nothing,
+            Action::Async(fut) => Action::Async(Box::pin(async move { f(fut.await) })),
+            Action::Stream(stream) => Action::Stream(Box::pin(Map { stream, f })),
+            Action::Batch(actions) => {
+                Action::Batch(actions.into_iter().map(|action| action.map(f)).collect())
+            }
+            Action::Sequence(actions) => {
+                Action::Sequence(actions.into_iter().map(|action| action.map(f)).collect())
+            }
+            Action::Keyed { key, action } => Action::Keyed {
+                key,
+                action: Box::new(action.map(f)),
+            },
+            Action::Cancel(key) => Action::Cancel(key),
+        }
+    }
+
+    /// Adds what this action does for the widget `wid` to `effects`.
+    pub(crate) fn effects(self, runtime: &Runtime, wid: &str, effects: &mut Effects) {
+        match self {
+            Action::Diff => effects.render = true,
+            Action::Nothing => {}
+            Action::Async(fut) => {
+                let runtime = runtime.clone();
+                let wid = wid.to_string();
+                let task: Task = Box::pin(async move {
+                    let msg = fut.await;
+                    runtime.update_widget(&wid, msg);
+                });
+                effects.tasks.push((None, task))
+            }
+            Action::Stream(mut stream) => {
+                let runtime = runtime.clone();
+                let wid = wid.to_string();
+                let task: Task = Box::pin(async move {
+                    while let Some(msg) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                        runtime.update_widget(&wid, msg);
+                    }
+                });
+                effects.tasks.push((None, task))
+            }
+            Action::Batch(actions) => {
+                for action in actions {
+                    action.effects(runtime, wid, effects)
+                }
+            }
+            Action::Sequence(actions) => {
+                let runtime = runtime.clone();
+                let wid = wid.to_string();
+                let task: Task = Box::pin(async move {
+                    for action in actions {
+                        let mut effects = Effects::default();
+                        action.effects(&runtime, &wid, &mut effects);
+                        for key in effects.cancel.iter() {
+                            runtime.cancel_tasks(&wid, Some(key));
+                        }
+                        if effects.render {
+                            runtime.schedule_render(&wid);
+                        }
+                        join(effects.tasks.into_iter().map(|(_, task)| task)).await;
+                    }
+                });
+                effects.tasks.push((None, task))
+            }
+            Action::Keyed { key, action } => {
+                // cancelled once for all of the tasks, which would abort
+                // each other otherwise
+                effects.cancel.push(key.clone());
+                let start = effects.tasks.len();
+                action.effects(runtime, wid, effects);
+                for (task_key, _) in effects.tasks[start..].iter_mut() {
+                    task_key.get_or_insert_with(|| key.clone());
+                }
+            }
+            Action::Cancel(key) => effects.cancel.push(key),
+        }
+    }
+}
+
+/// Runs `tasks` at once, until all of them are done.
+async fn join(tasks: impl Iterator<Item = Task>) {
+    let mut tasks: Vec<Option<Task>> = tasks.map(Some).collect();
+    poll_fn(|cx| {
+        let mut done = true;
+        for slot in tasks.iter_mut() {
+            if let Some(task) = slot {
+                match task.as_mut().poll(cx) {
+                    Poll::Ready(()) => *slot = None,
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// The stream of `Action::map`.
+struct Map<M, N> {
+    stream: Pin<Box<dyn Stream<Item = M>>>,
+    f: fn(M) -> N,
+}
+
+impl<M, N> Stream for Map<M, N> {
+    type Item = N;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<N>> {
+        let f = self.f;
+        self.stream.as_mut().poll_next(cx).map(|item| item.map(f))
+    }
 }
 
 impl<F, M> From<F> for Action<M>
@@ -14,3 +222,110 @@ where
         Action::Async(Box::pin(f))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        rc::Rc,
+        task::{Context, Poll},
+    };
+
+    use futures_core::Stream;
+    use wasm_rs_async_executor::single_threaded as executor;
+
+    use crate::api::*;
+
+    /// Shows the numbers it got, sorted as the executor picks the order
+    /// of the futures, running what `clicked` returns when clicked.
+    fn collect(clicked: fn() -> Action<u32>) -> Widget {
+        Widget::new(
+            "collect",
+            clicked,
+            Vec::new,
+            |got: &mut Vec<u32>, clicked: &fn() -> Action<u32>, n: u32| {
+                got.push(n);
+                match n {
+                    0 => Action::Diff.and(clicked()),
+                    _ => Action::Diff,
+                }
+            },
+            |got: &Vec<u32>, _: &fn() -> Action<u32>| -> Html<u32> {
+                let mut got = got.clone();
+                got.sort();
+                let got: Vec<_> = got.iter().map(u32::to_string).collect();
+                p().on("click", || 0).child(got.join(","))
+            },
+        )
+    }
+
+    /// Starts two futures under one key when clicked.
+    fn keyed() -> Widget {
+        collect(|| Action::batch([async { 1 }.into(), async { 2 }.into()]).key("k"))
+    }
+
+    #[test]
+    fn runs_every_future_of_a_keyed_batch() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(keyed);
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch(el, "click", MemoryEvent::default());
+        executor::run(None);
+        runtime.flush();
+        assert_eq!(memory.html(el), "<p>0,1,2</p>");
+
+        // a click while they are still running aborts them
+        memory.dispatch(el, "click", MemoryEvent::default());
+        memory.dispatch(el, "click", MemoryEvent::default());
+        executor::run(None);
+        runtime.flush();
+        assert_eq!(memory.html(el), "<p>0,0,0,1,1,2,2</p>");
+    }
+
+    #[test]
+    fn maps_the_messages_of_an_action() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| {
+            collect(|| {
+                Action::sequence([async { 1 }.into(), Action::Diff, async { 2 }.into()])
+                    .key("k")
+                    .map(|n: u32| n * 10)
+            })
+        });
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch(el, "click", MemoryEvent::default());
+        executor::run(None);
+        runtime.flush();
+        assert_eq!(memory.html(el), "<p>0,10,20</p>");
+    }
+
+    /// Counts down from a number to 1.
+    struct Countdown(u32);
+
+    impl Stream for Countdown {
+        type Item = u32;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<u32>> {
+            let n = self.0;
+            self.0 = n.saturating_sub(1);
+            Poll::Ready((n > 0).then_some(n))
+        }
+    }
+
+    #[test]
+    fn sends_every_item_of_a_stream() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| collect(|| Action::stream(Countdown(3)).map(|n: u32| n * 10)));
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch(el, "click", MemoryEvent::default());
+        executor::run(None);
+        runtime.flush();
+        assert_eq!(memory.html(el), "<p>0,10,20,30</p>");
+    }
+}
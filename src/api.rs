@@ -1,13 +1,64 @@
 pub use crate::{
     action::Action,
-    events::ListenerKind,
-    nodes::{element::Element as Html, widget::Widget},
+    backend::{
+        set_backend, Backend, Batched, CallbackId, Dom, Event, ListenerOptions, Memory,
+        MemoryEvent, NodeId, Target,
+    },
+    callback::Callback,
+    diff::diff,
+    draw::draw,
+    events::{Listener, ListenerKind, Timing},
+    hydrate::Mismatch,
+    nodes::{
+        element::Element as Html,
+        node::Node,
+        widget::{Widget, WidgetRoot},
+    },
+    patch::{apply as apply_patches, ListenerId, Patch},
+    runtime::Runtime,
+    sub::Sub,
     utils::log,
 };
 
-pub fn run<M: 'static>(w: Widget) {
-    let body = crate::utils::document().body().unwrap();
-    w.node::<M>().attach_borrow(&body);
+#[cfg(feature = "ssr")]
+pub use crate::ssr::{render_to_string, render_widget_to_string};
+
+/// Starts an app in a runtime of its own, attaching the widget `app`
+/// creates to the root of the backend.
+pub fn run(app: impl FnOnce() -> Widget) -> Runtime {
+    let runtime = Runtime::new();
+    runtime.enter(|| {
+        let backend = crate::backend::current();
+        let root = backend.root();
+        let mut app = app();
+        let mut patches = Vec::new();
+        app.mount(&mut patches);
+        crate::patch::apply(patches);
+        app.node::<()>().attach_borrow(root);
+        backend.mount(root);
+        runtime.commit();
+    });
+    runtime
+}
+
+/// Like `run`, but takes over the markup the server rendered into the
+/// element with id `mount`. When it doesn't match the view, the mismatches
+/// are logged and returned, and the widget is rendered into `mount` anew.
+pub fn hydrate(app: impl FnOnce() -> Widget, mount: &str) -> (Runtime, Vec<Mismatch>) {
+    let backend = crate::backend::current();
+    let mount = match backend.element_by_id(mount) {
+        Some(mount) => mount,
+        None => panic!("There is no element with id {} to hydrate", mount),
+    };
+    let runtime = Runtime::new();
+    let mismatches = runtime.enter(|| {
+        let mismatches = crate::hydrate::hydrate(&mut app(), mount);
+        backend.mount(mount);
+        backend.flush();
+        runtime.commit();
+        mismatches
+    });
+    (runtime, mismatches)
 }
 
 macro_rules! implement_tags {
@@ -73,8 +124,6 @@ implement_tags!(div, p, h1, h2, h3, h4, h5, h6, h7, img, a, button, input, form,
 // implement_operator!(BitAnd, bitand);
 // implement_operator!(BitOr, bitor);
 
-
-
 // macro_rules! implement_listeners {
 //         ( $( $listener:ident ),* ) => {
 //             $(
@@ -0,0 +1,287 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    rc::Rc,
+};
+
+use wasm_bindgen::{prelude::*, JsCast};
+
+use super::{
+    dom::{self, JsListener, Listeners},
+    next_id, Backend, CallbackId, Handler, ListenerOptions, NodeId, Target,
+};
+use crate::utils::document;
+
+#[wasm_bindgen(module = "/src/batch.js")]
+extern "C" {
+    #[wasm_bindgen(js_name = apply)]
+    fn apply_ops(ops: &[u32], text: &str, handlers: &js_sys::Array);
+
+    #[wasm_bindgen(js_name = adopt)]
+    fn adopt_node(id: u32, node: &web_sys::Node);
+
+    #[wasm_bindgen(js_name = node)]
+    fn get_node(id: u32) -> web_sys::Node;
+}
+
+// keep in sync with src/batch.js
+const CREATE_ELEMENT: u32 = 0;
+const CREATE_TEXT: u32 = 1;
+const SET_ATTRIBUTE: u32 = 2;
+const REMOVE_ATTRIBUTE: u32 = 3;
+const SET_TEXT: u32 = 4;
+const INSERT: u32 = 5;
+const REPLACE: u32 = 6;
+const REMOVE: u32 = 7;
+const LISTEN: u32 = 8;
+const UNLISTEN: u32 = 9;
+const RELEASE: u32 = 10;
+
+/// The browser dom, but instead of crossing into javascript for every
+/// change, changes are encoded as opcodes and applied by src/batch.js in
+/// a single call when the backend is flushed.
+#[derive(Default)]
+pub struct Batched {
+    buffer: RefCell<Buffer>,
+    root: OnceCell<NodeId>,
+    /// The listeners javascript is handed, kept alive until it drops them.
+    listeners: RefCell<Listeners<JsListener>>,
+    callbacks: dom::Callbacks,
+    delegation: Option<dom::Delegation>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    ops: Vec<u32>,
+    /// All strings of the batch, referred to by utf-16 offset and length.
+    text: String,
+    text_len: u32,
+    handlers: Vec<js_sys::Function>,
+    /// Listeners that are removed in this batch, freed once it is applied.
+    spent: Vec<JsListener>,
+}
+
+impl Buffer {
+    fn op(&mut self, op: u32, ids: &[NodeId]) {
+        self.ops.push(op);
+        self.ops.extend(ids.iter().map(|id| id.0));
+    }
+
+    fn str(&mut self, s: &str) {
+        let len = s.encode_utf16().count() as u32;
+        self.ops.push(self.text_len);
+        self.ops.push(len);
+        self.text.push_str(s);
+        self.text_len += len;
+    }
+}
+
+impl Batched {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `new`, but with one listener on every mount for every type of
+    /// event, as `Dom::delegated` has. Listeners then don't take up any
+    /// space in the batch.
+    pub fn delegated() -> Self {
+        Self {
+            delegation: Some(dom::Delegation::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Makes an existing dom node known to this backend.
+    pub fn adopt(&self, node: web_sys::Node) -> NodeId {
+        let id = next_id();
+        adopt_node(id.0, &node);
+        id
+    }
+
+    /// The browser node of `id`, once the changes that create it have
+    /// been flushed.
+    pub fn node(&self, id: NodeId) -> web_sys::Node {
+        get_node(id.0)
+    }
+}
+
+impl Backend for Batched {
+    fn root(&self) -> NodeId {
+        *self
+            .root
+            .get_or_init(|| self.adopt(document().body().unwrap().into()))
+    }
+
+    fn create_element(&self, id: NodeId, tag: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(CREATE_ELEMENT, &[id]);
+        buffer.str(tag);
+    }
+
+    fn create_text(&self, id: NodeId, data: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(CREATE_TEXT, &[id]);
+        buffer.str(data);
+    }
+
+    fn set_attribute(&self, element: NodeId, name: &str, val: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(SET_ATTRIBUTE, &[element]);
+        buffer.str(name);
+        buffer.str(val);
+    }
+
+    fn remove_attribute(&self, element: NodeId, name: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(REMOVE_ATTRIBUTE, &[element]);
+        buffer.str(name);
+    }
+
+    fn set_text(&self, text: NodeId, data: &str) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(SET_TEXT, &[text]);
+        buffer.str(data);
+    }
+
+    fn insert_child(&self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(INSERT, &[parent, child]);
+        buffer.ops.push(before.map_or(0, |before| before.0 + 1));
+    }
+
+    fn replace_child(&self, child: NodeId, by: NodeId) {
+        self.buffer.borrow_mut().op(REPLACE, &[child, by]);
+    }
+
+    fn remove_child(&self, child: NodeId) {
+        self.buffer.borrow_mut().op(REMOVE, &[child]);
+    }
+
+    fn release(&self, node: NodeId) {
+        if let Some(delegation) = &self.delegation {
+            delegation.release(node);
+        }
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(RELEASE, &[node]);
+        if let Some(listeners) = self.listeners.borrow_mut().remove(&node) {
+            buffer.spent.extend(listeners.into_values());
+        }
+    }
+
+    fn add_listener(
+        &self,
+        element: NodeId,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) {
+        if let Some(delegation) = &self.delegation {
+            return delegation.add(element, event, options, handler);
+        }
+        let cb = dom::listener(options, handler);
+
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(LISTEN, &[element]);
+        buffer.str(event);
+        let index = buffer.handlers.len() as u32;
+        buffer.ops.push(index);
+        buffer.ops.push(
+            options.capture as u32 | (options.passive as u32) << 1 | (options.once as u32) << 2,
+        );
+        buffer
+            .handlers
+            .push(cb.as_ref().unchecked_ref::<js_sys::Function>().clone());
+        let replaced = self
+            .listeners
+            .borrow_mut()
+            .entry(element)
+            .or_default()
+            .insert(event.to_string(), cb);
+        buffer.spent.extend(replaced);
+    }
+
+    fn remove_listener(&self, element: NodeId, event: &str) {
+        if let Some(delegation) = &self.delegation {
+            return delegation.remove(element, event);
+        }
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.op(UNLISTEN, &[element]);
+        buffer.str(event);
+        let removed = self
+            .listeners
+            .borrow_mut()
+            .get_mut(&element)
+            .and_then(|listeners| listeners.remove(event));
+        buffer.spent.extend(removed);
+    }
+
+    fn flush(&self) {
+        let buffer = self.buffer.take();
+        if buffer.ops.is_empty() {
+            return;
+        }
+        let handlers: js_sys::Array = buffer.handlers.iter().collect();
+        apply_ops(&buffer.ops, &buffer.text, &handlers);
+        // only now javascript doesn't call the spent listeners anymore
+        drop(buffer.spent);
+    }
+
+    fn set_interval(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        self.callbacks.set_interval(ms, handler)
+    }
+
+    fn set_timeout(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        self.callbacks.set_timeout(ms, handler)
+    }
+
+    fn request_animation_frame(&self, handler: Rc<dyn Fn(f64)>) -> CallbackId {
+        self.callbacks.request_animation_frame(handler)
+    }
+
+    fn add_global_listener(
+        &self,
+        target: Target,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) -> CallbackId {
+        self.callbacks
+            .add_global_listener(target, event, options, handler)
+    }
+
+    fn cancel(&self, callback: CallbackId) {
+        self.callbacks.cancel(callback)
+    }
+
+    fn mount(&self, mount: NodeId) {
+        if let Some(delegation) = &self.delegation {
+            delegation.mount(self.node(mount));
+        }
+    }
+
+    fn web_node(&self, node: NodeId) -> Option<web_sys::Node> {
+        Some(self.node(node))
+    }
+
+    fn element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.flush();
+        document()
+            .get_element_by_id(id)
+            .map(|element| self.adopt(element.into()))
+    }
+
+    fn children(&self, node: NodeId) -> Vec<NodeId> {
+        dom::children(&self.node(node), |child| self.adopt(child))
+    }
+
+    fn tag(&self, node: NodeId) -> Option<String> {
+        dom::tag(&self.node(node))
+    }
+
+    fn text(&self, node: NodeId) -> Option<String> {
+        dom::text(&self.node(node))
+    }
+
+    fn attributes(&self, element: NodeId) -> Vec<(String, String)> {
+        dom::attributes(&self.node(element))
+    }
+}
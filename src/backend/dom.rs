@@ -0,0 +1,809 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use wasm_bindgen::{prelude::Closure, JsCast};
+
+use super::{next_id, Backend, CallbackId, Event, Handler, ListenerOptions, NodeId, Target};
+use crate::utils::document;
+
+/// The browser dom, reached through `web_sys`.
+#[derive(Default)]
+pub struct Dom {
+    nodes: RefCell<HashMap<NodeId, web_sys::Node>>,
+    /// The listeners of every element, with whether they capture, as
+    /// removing them takes that as well.
+    listeners: RefCell<Listeners<(JsListener, bool)>>,
+    root: OnceCell<NodeId>,
+    callbacks: Callbacks,
+    delegation: Option<Delegation>,
+}
+
+/// What every element listens with, by event.
+pub(super) type Listeners<T> = HashMap<NodeId, HashMap<String, T>>;
+
+/// A listener handed to javascript. It is freed when dropped, so it is
+/// kept for as long as it is listening.
+pub(super) type JsListener = Closure<dyn FnMut(web_sys::Event)>;
+
+impl Dom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `new`, but instead of a listener on every element, the element
+    /// every app is mounted into gets one listener for every type of
+    /// event, which finds the handlers of the elements it passed. Adding
+    /// and removing listeners then doesn't cross into javascript at all.
+    pub fn delegated() -> Self {
+        Self {
+            delegation: Some(Delegation::default()),
+            ..Self::default()
+        }
+    }
+
+    /// Makes an existing dom node known to this backend.
+    pub fn adopt(&self, node: web_sys::Node) -> NodeId {
+        let id = next_id();
+        if self.delegation.is_some() {
+            stamp(&node, id);
+        }
+        self.nodes.borrow_mut().insert(id, node);
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> web_sys::Node {
+        self.nodes.borrow()[&id].clone()
+    }
+
+    fn element(&self, id: NodeId) -> web_sys::Element {
+        self.node(id).unchecked_into()
+    }
+}
+
+impl Backend for Dom {
+    fn root(&self) -> NodeId {
+        *self
+            .root
+            .get_or_init(|| self.adopt(document().body().unwrap().into()))
+    }
+
+    fn create_element(&self, id: NodeId, tag: &str) {
+        match document().create_element(tag) {
+            Ok(dom_element) => {
+                if self.delegation.is_some() {
+                    stamp(&dom_element, id);
+                }
+                self.nodes.borrow_mut().insert(id, dom_element.into());
+            }
+            Err(_) => todo!(),
+        }
+    }
+
+    fn create_text(&self, id: NodeId, data: &str) {
+        let dom_text = document().create_text_node(data);
+        self.nodes.borrow_mut().insert(id, dom_text.into());
+    }
+
+    fn set_attribute(&self, element: NodeId, name: &str, val: &str) {
+        match self.element(element).set_attribute(name, val) {
+            Ok(()) => (),
+            Err(_) => todo!(),
+        }
+    }
+
+    fn remove_attribute(&self, element: NodeId, name: &str) {
+        match self.element(element).remove_attribute(name) {
+            Ok(()) => (),
+            Err(_) => todo!(),
+        }
+    }
+
+    fn set_text(&self, text: NodeId, data: &str) {
+        self.node(text)
+            .unchecked_into::<web_sys::Text>()
+            .set_data(data);
+    }
+
+    fn insert_child(&self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+        let before = before.map(|before| self.node(before));
+        match self
+            .node(parent)
+            .insert_before(&self.node(child), before.as_ref())
+        {
+            Ok(_inserted_child) => (),
+            Err(_) => todo!(),
+        }
+    }
+
+    fn replace_child(&self, child: NodeId, by: NodeId) {
+        let child = self.node(child);
+        match child.parent_node() {
+            Some(parent) => match parent.replace_child(&self.node(by), &child) {
+                Ok(_replaced_node) => (),
+                Err(_) => todo!(),
+            },
+            None => todo!(),
+        }
+    }
+
+    fn remove_child(&self, child: NodeId) {
+        let child = self.node(child);
+        match child.parent_node() {
+            Some(parent) => match parent.remove_child(&child) {
+                Ok(_removed_child) => (),
+                Err(_) => todo!(),
+            },
+            None => todo!(),
+        }
+    }
+
+    fn release(&self, node: NodeId) {
+        if let Some(delegation) = &self.delegation {
+            delegation.release(node);
+        }
+        let dom_node = self.nodes.borrow_mut().remove(&node);
+        let listeners = self.listeners.borrow_mut().remove(&node);
+        if let (Some(dom_node), Some(listeners)) = (dom_node, listeners) {
+            // the closures are freed once they can't be called anymore
+            for (event, (cb, capture)) in listeners {
+                remove_event_listener(&dom_node, &event, &cb, capture);
+            }
+        }
+    }
+
+    fn add_listener(
+        &self,
+        element: NodeId,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) {
+        if let Some(delegation) = &self.delegation {
+            return delegation.add(element, event, options, handler);
+        }
+        self.remove_listener(element, event);
+
+        let cb = listener(options, handler);
+        match self
+            .node(element)
+            .add_event_listener_with_callback_and_add_event_listener_options(
+                event,
+                cb.as_ref().unchecked_ref(),
+                &add_event_listener_options(options),
+            ) {
+            Ok(()) => (),
+            Err(_) => todo!(),
+        };
+
+        self.listeners
+            .borrow_mut()
+            .entry(element)
+            .or_default()
+            .insert(event.to_string(), (cb, options.capture));
+    }
+
+    fn remove_listener(&self, element: NodeId, event: &str) {
+        if let Some(delegation) = &self.delegation {
+            return delegation.remove(element, event);
+        }
+        let cb = self
+            .listeners
+            .borrow_mut()
+            .get_mut(&element)
+            .and_then(|listeners| listeners.remove(event));
+        if let Some((cb, capture)) = cb {
+            remove_event_listener(&self.node(element), event, &cb, capture);
+        }
+    }
+
+    fn set_interval(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        self.callbacks.set_interval(ms, handler)
+    }
+
+    fn set_timeout(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        self.callbacks.set_timeout(ms, handler)
+    }
+
+    fn request_animation_frame(&self, handler: Rc<dyn Fn(f64)>) -> CallbackId {
+        self.callbacks.request_animation_frame(handler)
+    }
+
+    fn add_global_listener(
+        &self,
+        target: Target,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) -> CallbackId {
+        self.callbacks
+            .add_global_listener(target, event, options, handler)
+    }
+
+    fn cancel(&self, callback: CallbackId) {
+        self.callbacks.cancel(callback)
+    }
+
+    fn mount(&self, mount: NodeId) {
+        if let Some(delegation) = &self.delegation {
+            delegation.mount(self.node(mount));
+        }
+    }
+
+    fn web_node(&self, node: NodeId) -> Option<web_sys::Node> {
+        self.nodes.borrow().get(&node).cloned()
+    }
+
+    fn element_by_id(&self, id: &str) -> Option<NodeId> {
+        document()
+            .get_element_by_id(id)
+            .map(|element| self.adopt(element.into()))
+    }
+
+    fn children(&self, node: NodeId) -> Vec<NodeId> {
+        children(&self.node(node), |child| self.adopt(child))
+    }
+
+    fn tag(&self, node: NodeId) -> Option<String> {
+        tag(&self.node(node))
+    }
+
+    fn text(&self, node: NodeId) -> Option<String> {
+        text(&self.node(node))
+    }
+
+    fn attributes(&self, element: NodeId) -> Vec<(String, String)> {
+        attributes(&self.node(element))
+    }
+}
+
+// The reading side is shared with the batched backend, which keeps
+// its nodes on the javascript side.
+
+pub(super) fn children(
+    node: &web_sys::Node,
+    adopt: impl Fn(web_sys::Node) -> NodeId,
+) -> Vec<NodeId> {
+    let mut children = Vec::new();
+    let mut cursor = node.first_child();
+    while let Some(child) = cursor {
+        cursor = child.next_sibling();
+        match child.node_type() {
+            web_sys::Node::ELEMENT_NODE | web_sys::Node::TEXT_NODE => children.push(adopt(child)),
+            _ => (),
+        }
+    }
+    children
+}
+
+pub(super) fn tag(node: &web_sys::Node) -> Option<String> {
+    node.dyn_ref::<web_sys::Element>()
+        .map(|element| element.local_name())
+}
+
+pub(super) fn text(node: &web_sys::Node) -> Option<String> {
+    match node.node_type() {
+        web_sys::Node::TEXT_NODE => node.node_value(),
+        _ => None,
+    }
+}
+
+pub(super) fn attributes(element: &web_sys::Node) -> Vec<(String, String)> {
+    let element: &web_sys::Element = element.unchecked_ref();
+    element
+        .get_attribute_names()
+        .iter()
+        .filter_map(|name| name.as_string())
+        .map(|name| {
+            let val = element.get_attribute(&name).unwrap_or_default();
+            (name, val)
+        })
+        .collect()
+}
+
+/// The value of the input, textarea or select the event was dispatched on.
+pub(super) fn target_value(e: &web_sys::Event) -> String {
+    let target = event_target_element(e);
+    if let Some(input) = target.dyn_ref::<web_sys::HtmlInputElement>() {
+        input.value()
+    } else if let Some(textarea) = target.dyn_ref::<web_sys::HtmlTextAreaElement>() {
+        textarea.value()
+    } else if let Some(select) = target.dyn_ref::<web_sys::HtmlSelectElement>() {
+        select.value()
+    } else {
+        panic!(
+            "on_value needs an input, textarea or select element, not <{}>",
+            target.tag_name().to_lowercase()
+        )
+    }
+}
+
+/// Whether the checkbox or radio button the event was dispatched on is checked.
+pub(super) fn target_checked(e: &web_sys::Event) -> bool {
+    let target = event_target_element(e);
+    match target.dyn_ref::<web_sys::HtmlInputElement>() {
+        Some(input) if matches!(input.type_().as_str(), "checkbox" | "radio") => input.checked(),
+        Some(input) => panic!(
+            "on_checked needs an input element of type checkbox or radio, not {}",
+            input.type_()
+        ),
+        None => panic!(
+            "on_checked needs an input element, not <{}>",
+            target.tag_name().to_lowercase()
+        ),
+    }
+}
+
+/// The files picked with the file input the event was dispatched on.
+pub(super) fn target_files(e: &web_sys::Event) -> Vec<web_sys::File> {
+    let target = event_target_element(e);
+    let files = target
+        .dyn_ref::<web_sys::HtmlInputElement>()
+        .and_then(|input| input.files());
+    let Some(files) = files else {
+        panic!(
+            "on_files needs an input element of type file, not <{}>",
+            target.tag_name().to_lowercase()
+        )
+    };
+    (0..files.length()).filter_map(|i| files.get(i)).collect()
+}
+
+fn event_target_element(e: &web_sys::Event) -> web_sys::Element {
+    e.target()
+        .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+        .expect("This event wasn't dispatched on an element")
+}
+
+/// The javascript function calling `handler`, shared with the batched
+/// backend. It does what `options` asks of the listener itself first.
+pub(super) fn listener(options: ListenerOptions, handler: Handler) -> JsListener {
+    Closure::new(move |e: web_sys::Event| {
+        before_handler(&e, options);
+        handler(Event::Dom(e));
+    })
+}
+
+fn before_handler(e: &web_sys::Event, options: ListenerOptions) {
+    // browsers ignore preventing the default from passive listeners
+    if options.prevent_default && !options.passive {
+        e.prevent_default();
+    }
+    if options.stop_propagation {
+        e.stop_propagation();
+    }
+}
+
+fn add_event_listener_options(options: ListenerOptions) -> web_sys::AddEventListenerOptions {
+    let dom_options = web_sys::AddEventListenerOptions::new();
+    dom_options.set_capture(options.capture);
+    dom_options.set_passive(options.passive);
+    dom_options.set_once(options.once);
+    dom_options
+}
+
+fn remove_event_listener(node: &web_sys::Node, event: &str, cb: &JsListener, capture: bool) {
+    match node.remove_event_listener_with_callback_and_bool(
+        event,
+        cb.as_ref().unchecked_ref(),
+        capture,
+    ) {
+        Ok(()) => (),
+        Err(e) => panic!(
+            "Couldn't stop listening to {} on a dom node: {:?}",
+            event, e
+        ),
+    }
+}
+
+/// Listeners of a delegating backend, shared with the batched backend.
+/// The handlers stay on the rust side, and every mount gets one listener
+/// for every type of event, which walks up from the target of an event
+/// calling the handlers of the elements it passes, like bubbling does.
+#[derive(Default)]
+pub(super) struct Delegation {
+    handlers: Rc<RefCell<Listeners<(ListenerOptions, Handler)>>>,
+    /// The elements apps are mounted into.
+    mounts: RefCell<Vec<web_sys::Node>>,
+    /// The listeners of the mounts, by the type of events they listen to.
+    listeners: RefCell<HashMap<String, Vec<(web_sys::Node, JsListener)>>>,
+}
+
+/// The property elements of a delegating backend keep their id in.
+const NODE_ID: &str = "__lollipop";
+
+/// Marks `node` with its id, so that delegated events can find its handlers.
+pub(super) fn stamp(node: &web_sys::Node, id: NodeId) {
+    match js_sys::Reflect::set(node, &NODE_ID.into(), &id.0.into()) {
+        Ok(_) => (),
+        Err(e) => panic!("Couldn't stamp {:?} on its dom node: {:?}", id, e),
+    }
+}
+
+fn stamped(node: &web_sys::Node) -> Option<NodeId> {
+    js_sys::Reflect::get(node, &NODE_ID.into())
+        .ok()
+        .and_then(|id| id.as_f64())
+        .map(|id| NodeId(id as u32))
+}
+
+impl Delegation {
+    /// Sets the handler for `event` on `element`, first making the mounts
+    /// listen to `event` if they don't yet.
+    pub(super) fn add(
+        &self,
+        element: NodeId,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) {
+        self.handlers
+            .borrow_mut()
+            .entry(element)
+            .or_default()
+            .insert(event.to_string(), (options, handler));
+        if !self.listeners.borrow().contains_key(event) {
+            let listeners = self
+                .mounts
+                .borrow()
+                .iter()
+                .map(|mount| self.listen(mount, event))
+                .collect();
+            self.listeners
+                .borrow_mut()
+                .insert(event.to_string(), listeners);
+        }
+    }
+
+    /// Makes `mount`, which an app is mounted into, listen to the events
+    /// that have handlers, and to those that get them later.
+    pub(super) fn mount(&self, mount: web_sys::Node) {
+        if self.mounts.borrow().contains(&mount) {
+            return;
+        }
+        for (event, listeners) in self.listeners.borrow_mut().iter_mut() {
+            listeners.push(self.listen(&mount, event));
+        }
+        self.mounts.borrow_mut().push(mount);
+    }
+
+    pub(super) fn remove(&self, element: NodeId, event: &str) {
+        if let Some(handlers) = self.handlers.borrow_mut().get_mut(&element) {
+            handlers.remove(event);
+        }
+    }
+
+    pub(super) fn release(&self, node: NodeId) {
+        self.handlers.borrow_mut().remove(&node);
+    }
+
+    /// Makes `mount` listen to `event`, returning the listener.
+    fn listen(&self, mount: &web_sys::Node, event: &str) -> (web_sys::Node, JsListener) {
+        let handlers = self.handlers.clone();
+        let event_type = event.to_string();
+        let top = mount.clone();
+        let cb: JsListener = Closure::new(move |e: web_sys::Event| {
+            // the elements from the target up to the mount
+            let mut path = Vec::new();
+            let mut cursor: Option<web_sys::Node> = e.target().and_then(|t| t.dyn_into().ok());
+            while let Some(node) = cursor {
+                cursor = if node == top {
+                    None
+                } else {
+                    node.parent_node()
+                };
+                if let Some(id) = stamped(&node) {
+                    path.push(id);
+                }
+            }
+            delegate(
+                &handlers,
+                &path,
+                &event_type,
+                e.bubbles(),
+                |options, handler| {
+                    before_handler(&e, options);
+                    handler(Event::Dom(e.clone()));
+                    e.cancel_bubble()
+                },
+            );
+        });
+        // capturing, so that events that don't bubble, like focus, pass
+        // the mount as well
+        match mount.add_event_listener_with_callback_and_bool(
+            event,
+            cb.as_ref().unchecked_ref(),
+            true,
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Couldn't delegate {} events to a mount: {:?}", event, e),
+        }
+        (mount.clone(), cb)
+    }
+}
+
+/// Calls the handlers for `event` of the elements on `path`, which goes
+/// from the target up: the capturing ones on the way down, and then the
+/// others on the way up, or only the one of the target when the event
+/// doesn't bubble. `call` runs a handler, and returns whether it stopped
+/// the event from going further.
+fn delegate(
+    handlers: &RefCell<Listeners<(ListenerOptions, Handler)>>,
+    path: &[NodeId],
+    event: &str,
+    bubbles: bool,
+    mut call: impl FnMut(ListenerOptions, Handler) -> bool,
+) {
+    let capturing = path.iter().rev().map(|id| (*id, true));
+    let bubbling = path
+        .iter()
+        .take(if bubbles { path.len() } else { 1 })
+        .map(|id| (*id, false));
+    for (id, capture) in capturing.chain(bubbling) {
+        // the handler may change the listeners, so nothing can stay
+        // borrowed
+        let handler = handlers
+            .borrow()
+            .get(&id)
+            .and_then(|handlers| handlers.get(event).cloned());
+        let Some((options, handler)) = handler else {
+            continue;
+        };
+        if options.capture != capture {
+            continue;
+        }
+        if options.once {
+            if let Some(handlers) = handlers.borrow_mut().get_mut(&id) {
+                handlers.remove(event);
+            }
+        }
+        if call(options, handler) {
+            break;
+        }
+    }
+}
+
+/// The mounts stop listening along with the backend, as the closures
+/// are freed.
+impl Drop for Delegation {
+    fn drop(&mut self) {
+        for (event, listeners) in self.listeners.take() {
+            for (mount, cb) in listeners {
+                remove_event_listener(&mount, &event, &cb, true);
+            }
+        }
+    }
+}
+
+/// The timers, animation frames and global listeners of the browser,
+/// shared with the batched backend.
+#[derive(Default)]
+pub(super) struct Callbacks {
+    // shared with the timeouts and animation frames, which forget
+    // themselves once run
+    callbacks: Rc<RefCell<HashMap<CallbackId, Callback>>>,
+}
+
+/// The browser handles of the callbacks, along with their closures,
+/// which are freed when cancelled or, for those that run once, when run.
+enum Callback {
+    Interval(i32, Closure<dyn FnMut()>),
+    Timeout(i32, Closure<dyn FnMut()>),
+    Frame(i32, Closure<dyn FnMut(f64)>),
+    Listener {
+        target: Target,
+        event: String,
+        cb: JsListener,
+        capture: bool,
+    },
+}
+
+impl Callbacks {
+    pub(super) fn set_interval(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        let cb: Closure<dyn FnMut()> = Closure::new(move || handler());
+        let handle = match window().set_interval_with_callback_and_timeout_and_arguments_0(
+            cb.as_ref().unchecked_ref(),
+            ms as i32,
+        ) {
+            Ok(handle) => handle,
+            Err(e) => panic!("Couldn't set an interval of {} ms: {:?}", ms, e),
+        };
+        self.insert(Callback::Interval(handle, cb))
+    }
+
+    pub(super) fn set_timeout(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        let id = CallbackId(next_id().0);
+        let callbacks = Rc::downgrade(&self.callbacks);
+        let cb: Closure<dyn FnMut()> = Closure::once(move || {
+            // freeing a closure while it runs is fine, javascript lets
+            // it finish first
+            let _spent = callbacks.upgrade().and_then(|c| c.borrow_mut().remove(&id));
+            handler();
+        });
+        let handle = match window().set_timeout_with_callback_and_timeout_and_arguments_0(
+            cb.as_ref().unchecked_ref(),
+            ms as i32,
+        ) {
+            Ok(handle) => handle,
+            Err(e) => panic!("Couldn't set a timeout of {} ms: {:?}", ms, e),
+        };
+        self.callbacks
+            .borrow_mut()
+            .insert(id, Callback::Timeout(handle, cb));
+        id
+    }
+
+    pub(super) fn request_animation_frame(&self, handler: Rc<dyn Fn(f64)>) -> CallbackId {
+        let id = CallbackId(next_id().0);
+        let callbacks = Rc::downgrade(&self.callbacks);
+        let cb: Closure<dyn FnMut(f64)> = Closure::once(move |timestamp: f64| {
+            let _spent = callbacks.upgrade().and_then(|c| c.borrow_mut().remove(&id));
+            handler(timestamp);
+        });
+        let handle = match window().request_animation_frame(cb.as_ref().unchecked_ref()) {
+            Ok(handle) => handle,
+            Err(e) => panic!("Couldn't request an animation frame: {:?}", e),
+        };
+        self.callbacks
+            .borrow_mut()
+            .insert(id, Callback::Frame(handle, cb));
+        id
+    }
+
+    pub(super) fn add_global_listener(
+        &self,
+        target: Target,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) -> CallbackId {
+        let cb = listener(options, handler);
+        match event_target(target).add_event_listener_with_callback_and_add_event_listener_options(
+            event,
+            cb.as_ref().unchecked_ref(),
+            &add_event_listener_options(options),
+        ) {
+            Ok(()) => (),
+            Err(e) => panic!("Couldn't listen to {} on the {:?}: {:?}", event, target, e),
+        }
+        self.insert(Callback::Listener {
+            target,
+            event: event.to_string(),
+            cb,
+            capture: options.capture,
+        })
+    }
+
+    pub(super) fn cancel(&self, callback: CallbackId) {
+        let callback = self.callbacks.borrow_mut().remove(&callback);
+        // the closures are freed once the browser won't call them anymore
+        match callback {
+            Some(Callback::Interval(handle, cb)) => {
+                window().clear_interval_with_handle(handle);
+                drop(cb)
+            }
+            Some(Callback::Timeout(handle, cb)) => {
+                window().clear_timeout_with_handle(handle);
+                drop(cb)
+            }
+            Some(Callback::Frame(handle, cb)) => {
+                match window().cancel_animation_frame(handle) {
+                    Ok(()) => (),
+                    Err(e) => panic!("Couldn't cancel an animation frame: {:?}", e),
+                }
+                drop(cb)
+            }
+            Some(Callback::Listener {
+                target,
+                event,
+                cb,
+                capture,
+            }) => match event_target(target).remove_event_listener_with_callback_and_bool(
+                &event,
+                cb.as_ref().unchecked_ref(),
+                capture,
+            ) {
+                Ok(()) => (),
+                Err(e) => panic!(
+                    "Couldn't stop listening to {} on the {:?}: {:?}",
+                    event, target, e
+                ),
+            },
+            None => (),
+        }
+    }
+
+    fn insert(&self, callback: Callback) -> CallbackId {
+        let id = CallbackId(next_id().0);
+        self.callbacks.borrow_mut().insert(id, callback);
+        id
+    }
+}
+
+fn window() -> web_sys::Window {
+    web_sys::window().unwrap()
+}
+
+fn event_target(target: Target) -> web_sys::EventTarget {
+    match target {
+        Target::Window => window().into(),
+        Target::Document => document().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{delegate, Listeners};
+    use crate::backend::{Handler, ListenerOptions, MemoryEvent, NodeId};
+
+    thread_local! {
+        /// The handlers that were called, in order.
+        static CALLED: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn handler(name: &'static str) -> Handler {
+        Rc::new(move |_| CALLED.with(|called| called.borrow_mut().push(name)))
+    }
+
+    /// Delegates a click from `path`, stopping after handlers that ask
+    /// to, and returns the handlers that were called.
+    fn click(
+        handlers: &RefCell<Listeners<(ListenerOptions, Handler)>>,
+        path: &[NodeId],
+        bubbles: bool,
+    ) -> Vec<&'static str> {
+        delegate(handlers, path, "click", bubbles, |options, handler| {
+            handler(crate::backend::Event::Memory(MemoryEvent::default()));
+            options.stop_propagation
+        });
+        CALLED.with(|called| called.take())
+    }
+
+    #[test]
+    fn delegates_like_the_dom_dispatches() {
+        let (target, parent, outer) = (NodeId(0), NodeId(1), NodeId(2));
+        let capture = ListenerOptions {
+            capture: true,
+            ..Default::default()
+        };
+        let once = ListenerOptions {
+            once: true,
+            ..Default::default()
+        };
+        let handlers = RefCell::new(Listeners::new());
+        let add = |id, options, name| {
+            let mut handlers = handlers.borrow_mut();
+            let handlers = handlers.entry(id).or_insert_with(Default::default);
+            handlers.insert("click".to_string(), (options, handler(name)));
+        };
+        add(outer, capture, "outer capturing");
+        add(parent, once, "parent once");
+        add(target, ListenerOptions::default(), "target");
+        let path = [target, parent, outer];
+
+        assert_eq!(
+            click(&handlers, &path, true),
+            ["outer capturing", "target", "parent once"]
+        );
+        assert_eq!(click(&handlers, &path, true), ["outer capturing", "target"]);
+        assert_eq!(click(&handlers, &path[1..], false), ["outer capturing"]);
+
+        let stop = ListenerOptions {
+            stop_propagation: true,
+            capture: true,
+            ..Default::default()
+        };
+        handlers
+            .borrow_mut()
+            .get_mut(&outer)
+            .unwrap()
+            .get_mut("click")
+            .unwrap()
+            .0 = stop;
+        assert_eq!(click(&handlers, &path, true), ["outer capturing"]);
+    }
+}
@@ -0,0 +1,577 @@
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    rc::Rc,
+};
+
+use super::{next_id, Backend, CallbackId, Event, Handler, ListenerOptions, NodeId, Target};
+
+/// A dom that only lives in memory, so that trees can be drawn, diffed
+/// and clicked through outside of a browser. Time stands still until
+/// it is advanced, and animation frames only run when asked to.
+#[derive(Default)]
+pub struct Memory {
+    nodes: RefCell<HashMap<NodeId, MemoryNode>>,
+    root: OnceCell<NodeId>,
+    callbacks: RefCell<HashMap<CallbackId, Callback>>,
+    /// The virtual clock, in milliseconds.
+    now: Cell<f64>,
+}
+
+/// The data an event carries when dispatched on the memory backend.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryEvent {
+    pub value: Option<String>,
+    pub checked: Option<bool>,
+}
+
+struct MemoryNode {
+    data: Data,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    listeners: HashMap<String, (ListenerOptions, Handler)>,
+}
+
+enum Callback {
+    Interval {
+        ms: u32,
+        next: f64,
+        handler: Rc<dyn Fn()>,
+    },
+    Timeout {
+        at: f64,
+        handler: Rc<dyn Fn()>,
+    },
+    Frame(Rc<dyn Fn(f64)>),
+    Listener {
+        target: Target,
+        event: String,
+        options: ListenerOptions,
+        handler: Handler,
+    },
+}
+
+enum Data {
+    Element {
+        tag: String,
+        attrs: BTreeMap<String, String>,
+    },
+    Text(String),
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attribute(&self, element: NodeId, name: &str) -> Option<String> {
+        match &self.nodes.borrow()[&element].data {
+            Data::Element { attrs, .. } => attrs.get(name).cloned(),
+            Data::Text(_) => None,
+        }
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.nodes.borrow()[&node].parent
+    }
+
+    /// The number of nodes that haven't been released, to check that
+    /// nothing is left behind.
+    pub fn node_count(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn has_listener(&self, element: NodeId, event: &str) -> bool {
+        self.nodes.borrow()[&element].listeners.contains_key(event)
+    }
+
+    /// Calls the listeners for `event` on `target` and its ancestors,
+    /// like a bubbling dom event would: the capturing ones from the root
+    /// down, then the others from `target` up. Returns whether one of
+    /// them prevented the default.
+    pub fn dispatch(&self, target: NodeId, event: &str, data: MemoryEvent) -> bool {
+        let mut path = Vec::new();
+        let mut cursor = Some(target);
+        while let Some(node) = cursor {
+            path.push(node);
+            cursor = self.nodes.borrow()[&node].parent;
+        }
+        let capturing = path.iter().rev().map(|node| (*node, true));
+        let bubbling = path.iter().map(|node| (*node, false));
+        let mut prevented = false;
+        for (node, capture) in capturing.chain(bubbling) {
+            // the handler may update the tree, so nothing can stay borrowed
+            let listener = {
+                let nodes = self.nodes.borrow();
+                let Some(node) = nodes.get(&node) else {
+                    continue;
+                };
+                node.listeners.get(event).cloned()
+            };
+            let Some((options, handler)) = listener else {
+                continue;
+            };
+            if options.capture != capture {
+                continue;
+            }
+            if options.once {
+                self.remove_listener(node, event);
+            }
+            prevented |= options.prevent_default && !options.passive;
+            handler(Event::Memory(data.clone()));
+            if options.stop_propagation {
+                break;
+            }
+        }
+        prevented
+    }
+
+    /// Like `dispatch`, but for an event on the window or document, which
+    /// passes it on to the window. Unlike elements, they can have several
+    /// listeners for an event, which are called in the order they were
+    /// added, even when one of them stops the propagation.
+    pub fn dispatch_global(&self, target: Target, event: &str, data: MemoryEvent) -> bool {
+        let path = match target {
+            Target::Document => vec![Target::Document, Target::Window],
+            Target::Window => vec![Target::Window],
+        };
+        let capturing = path.iter().rev().map(|target| (*target, true));
+        let bubbling = path.iter().map(|target| (*target, false));
+        let mut prevented = false;
+        let mut stopped_at = None;
+        for (target, capture) in capturing.chain(bubbling) {
+            if stopped_at.is_some_and(|stopped_at| stopped_at != target) {
+                break;
+            }
+            for (id, options, handler) in self.global_listeners(target, event) {
+                if options.capture != capture {
+                    continue;
+                }
+                if options.once {
+                    self.cancel(id);
+                }
+                prevented |= options.prevent_default && !options.passive;
+                handler(Event::Memory(data.clone()));
+                if options.stop_propagation {
+                    stopped_at = Some(target);
+                }
+            }
+        }
+        prevented
+    }
+
+    pub fn has_global_listener(&self, target: Target, event: &str) -> bool {
+        !self.global_listeners(target, event).is_empty()
+    }
+
+    /// The listeners for `event` on `target`, in the order they were added.
+    fn global_listeners(
+        &self,
+        target: Target,
+        event: &str,
+    ) -> Vec<(CallbackId, ListenerOptions, Handler)> {
+        let mut listeners: Vec<_> = self
+            .callbacks
+            .borrow()
+            .iter()
+            .filter_map(|(id, callback)| match callback {
+                Callback::Listener {
+                    target: t,
+                    event: e,
+                    options,
+                    handler,
+                } if *t == target && e == event => Some((*id, *options, handler.clone())),
+                _ => None,
+            })
+            .collect();
+        listeners.sort_by_key(|(id, _, _)| id.0);
+        listeners
+    }
+
+    /// The time on the virtual clock, in milliseconds.
+    pub fn now(&self) -> f64 {
+        self.now.get()
+    }
+
+    /// Moves the virtual clock `ms` milliseconds forward, running the
+    /// intervals and timeouts that come due on the way in order.
+    pub fn advance(&self, ms: u32) {
+        let until = self.now.get() + ms as f64;
+        loop {
+            let due = {
+                let mut callbacks = self.callbacks.borrow_mut();
+                let earliest = callbacks
+                    .iter()
+                    .filter_map(|(id, callback)| match callback {
+                        Callback::Interval { next: at, .. } | Callback::Timeout { at, .. }
+                            if *at <= until =>
+                        {
+                            Some((*at, *id))
+                        }
+                        _ => None,
+                    })
+                    // those due at the same time in the order they were set
+                    .min_by(|(a, a_id), (b, b_id)| a.total_cmp(b).then(a_id.0.cmp(&b_id.0)));
+                earliest.map(|(due, id)| {
+                    let handler = match callbacks.get_mut(&id) {
+                        Some(Callback::Interval { ms, next, handler }) => {
+                            *next += *ms as f64;
+                            handler.clone()
+                        }
+                        _ => match callbacks.remove(&id) {
+                            Some(Callback::Timeout { handler, .. }) => handler,
+                            _ => unreachable!(),
+                        },
+                    };
+                    (due, handler)
+                })
+            };
+            match due {
+                Some((due, handler)) => {
+                    self.now.set(due);
+                    handler();
+                }
+                None => break,
+            }
+        }
+        self.now.set(until);
+    }
+
+    /// Runs the animation frames requested so far. Frames requested while
+    /// they run wait for the next call.
+    pub fn animation_frame(&self) {
+        let mut frames = Vec::new();
+        self.callbacks
+            .borrow_mut()
+            .retain(|id, callback| match callback {
+                Callback::Frame(handler) => {
+                    frames.push((*id, handler.clone()));
+                    false
+                }
+                _ => true,
+            });
+        // in the order they were requested
+        frames.sort_by_key(|(id, _)| id.0);
+        for (_, handler) in frames {
+            handler(self.now.get());
+        }
+    }
+
+    /// Serializes `node` and its children to html, with sorted attributes.
+    pub fn html(&self, node: NodeId) -> String {
+        let mut out = String::new();
+        self.write_html(node, &mut out);
+        out
+    }
+
+    fn write_html(&self, node: NodeId, out: &mut String) {
+        let (tag, children) = {
+            let nodes = self.nodes.borrow();
+            let node = &nodes[&node];
+            match &node.data {
+                Data::Text(data) => return out.push_str(data),
+                Data::Element { tag, attrs } => {
+                    let _ = write!(out, "<{}", tag);
+                    for (name, val) in attrs {
+                        let _ = write!(out, " {}=\"{}\"", name, val);
+                    }
+                    out.push('>');
+                    (tag.clone(), node.children.clone())
+                }
+            }
+        };
+        for child in children {
+            self.write_html(child, out);
+        }
+        let _ = write!(out, "</{}>", tag);
+    }
+
+    fn insert(&self, id: NodeId, data: Data) {
+        self.nodes.borrow_mut().insert(
+            id,
+            MemoryNode {
+                data,
+                parent: None,
+                children: Vec::new(),
+                listeners: HashMap::new(),
+            },
+        );
+    }
+
+    fn insert_callback(&self, callback: Callback) -> CallbackId {
+        let id = CallbackId(next_id().0);
+        self.callbacks.borrow_mut().insert(id, callback);
+        id
+    }
+
+    fn detach(&self, child: NodeId) {
+        let mut nodes = self.nodes.borrow_mut();
+        if let Some(parent) = node(&mut nodes, child, "detach").parent.take() {
+            node(&mut nodes, parent, "detach a child of")
+                .children
+                .retain(|&c| c != child);
+        }
+    }
+}
+
+/// The node `id`, for the operation that needs it alive, like a node
+/// that hasn't been released yet.
+fn node<'a>(
+    nodes: &'a mut HashMap<NodeId, MemoryNode>,
+    id: NodeId,
+    operation: &str,
+) -> &'a mut MemoryNode {
+    match nodes.get_mut(&id) {
+        Some(node) => node,
+        None => panic!(
+            "Can't {} {:?}, which was never created or has been released",
+            operation, id
+        ),
+    }
+}
+
+impl Backend for Memory {
+    fn root(&self) -> NodeId {
+        *self.root.get_or_init(|| {
+            let root = next_id();
+            self.create_element(root, "body");
+            root
+        })
+    }
+
+    fn create_element(&self, id: NodeId, tag: &str) {
+        self.insert(
+            id,
+            Data::Element {
+                tag: tag.to_string(),
+                attrs: BTreeMap::new(),
+            },
+        )
+    }
+
+    fn create_text(&self, id: NodeId, data: &str) {
+        self.insert(id, Data::Text(data.to_string()))
+    }
+
+    fn set_attribute(&self, element: NodeId, name: &str, val: &str) {
+        let mut nodes = self.nodes.borrow_mut();
+        match &mut node(&mut nodes, element, "set an attribute on").data {
+            Data::Element { attrs, .. } => {
+                attrs.insert(name.to_string(), val.to_string());
+            }
+            Data::Text(_) => panic!("Can't set attribute {} on text {:?}", name, element),
+        }
+    }
+
+    fn remove_attribute(&self, element: NodeId, name: &str) {
+        let mut nodes = self.nodes.borrow_mut();
+        match &mut node(&mut nodes, element, "remove an attribute from").data {
+            Data::Element { attrs, .. } => {
+                attrs.remove(name);
+            }
+            Data::Text(_) => panic!("Can't remove attribute {} from text {:?}", name, element),
+        }
+    }
+
+    fn set_text(&self, text: NodeId, data: &str) {
+        let mut nodes = self.nodes.borrow_mut();
+        match &mut node(&mut nodes, text, "set the text of").data {
+            Data::Text(old) => *old = data.to_string(),
+            Data::Element { .. } => panic!("Can't set the text of element {:?}", text),
+        }
+    }
+
+    fn insert_child(&self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+        self.detach(child);
+        let mut nodes = self.nodes.borrow_mut();
+        let children = &mut node(&mut nodes, parent, "insert a child into").children;
+        let index = match before {
+            Some(before) => match children.iter().position(|&c| c == before) {
+                Some(index) => index,
+                None => panic!(
+                    "Can't insert {:?} into {:?} before {:?}, which isn't a child of it",
+                    child, parent, before
+                ),
+            },
+            None => children.len(),
+        };
+        children.insert(index, child);
+        node(&mut nodes, child, "insert").parent = Some(parent);
+    }
+
+    fn replace_child(&self, child: NodeId, by: NodeId) {
+        self.detach(by);
+        let mut nodes = self.nodes.borrow_mut();
+        let parent = match node(&mut nodes, child, "replace").parent.take() {
+            Some(parent) => parent,
+            None => panic!(
+                "Can't replace {:?} by {:?}, as it isn't in the tree",
+                child, by
+            ),
+        };
+        let children = &mut node(&mut nodes, parent, "replace a child of").children;
+        for c in children.iter_mut() {
+            if *c == child {
+                *c = by;
+            }
+        }
+        node(&mut nodes, by, "replace with").parent = Some(parent);
+    }
+
+    fn remove_child(&self, child: NodeId) {
+        let mut nodes = self.nodes.borrow_mut();
+        if node(&mut nodes, child, "remove").parent.is_none() {
+            panic!("Can't remove {:?}, as it isn't in the tree", child)
+        }
+        drop(nodes);
+        self.detach(child);
+    }
+
+    fn release(&self, node: NodeId) {
+        let mut nodes = self.nodes.borrow_mut();
+        if let Some(released) = nodes.remove(&node) {
+            if let Some(parent) = released.parent.and_then(|parent| nodes.get_mut(&parent)) {
+                parent.children.retain(|&c| c != node);
+            }
+        }
+    }
+
+    fn add_listener(
+        &self,
+        element: NodeId,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) {
+        let mut nodes = self.nodes.borrow_mut();
+        let operation = format!("listen to {} on", event);
+        node(&mut nodes, element, &operation)
+            .listeners
+            .insert(event.to_string(), (options, handler));
+    }
+
+    fn remove_listener(&self, element: NodeId, event: &str) {
+        let mut nodes = self.nodes.borrow_mut();
+        let operation = format!("stop listening to {} on", event);
+        node(&mut nodes, element, &operation)
+            .listeners
+            .remove(event);
+    }
+
+    fn set_interval(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        // like browsers, which run intervals of 0 ms as ones of 1 ms, so
+        // that they can't run forever without time going by
+        let ms = ms.max(1);
+        self.insert_callback(Callback::Interval {
+            ms,
+            next: self.now.get() + ms as f64,
+            handler,
+        })
+    }
+
+    fn set_timeout(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId {
+        self.insert_callback(Callback::Timeout {
+            at: self.now.get() + ms as f64,
+            handler,
+        })
+    }
+
+    fn request_animation_frame(&self, handler: Rc<dyn Fn(f64)>) -> CallbackId {
+        self.insert_callback(Callback::Frame(handler))
+    }
+
+    fn add_global_listener(
+        &self,
+        target: Target,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) -> CallbackId {
+        self.insert_callback(Callback::Listener {
+            target,
+            event: event.to_string(),
+            options,
+            handler,
+        })
+    }
+
+    fn cancel(&self, callback: CallbackId) {
+        self.callbacks.borrow_mut().remove(&callback);
+    }
+
+    fn element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.nodes
+            .borrow()
+            .iter()
+            .find_map(|(node, memory_node)| match &memory_node.data {
+                Data::Element { attrs, .. } if attrs.get("id").map(|v| &**v) == Some(id) => {
+                    Some(*node)
+                }
+                _ => None,
+            })
+    }
+
+    fn children(&self, node: NodeId) -> Vec<NodeId> {
+        self.nodes.borrow()[&node].children.clone()
+    }
+
+    fn tag(&self, node: NodeId) -> Option<String> {
+        match &self.nodes.borrow()[&node].data {
+            Data::Element { tag, .. } => Some(tag.clone()),
+            Data::Text(_) => None,
+        }
+    }
+
+    fn text(&self, node: NodeId) -> Option<String> {
+        match &self.nodes.borrow()[&node].data {
+            Data::Text(data) => Some(data.clone()),
+            Data::Element { .. } => None,
+        }
+    }
+
+    fn attributes(&self, element: NodeId) -> Vec<(String, String)> {
+        match &self.nodes.borrow()[&element].data {
+            Data::Element { attrs, .. } => attrs
+                .iter()
+                .map(|(name, val)| (name.clone(), val.clone()))
+                .collect(),
+            Data::Text(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::Memory;
+    use crate::backend::{next_id, Backend};
+
+    #[test]
+    fn runs_intervals_of_zero_ms_once_per_ms() {
+        let memory = Memory::new();
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let interval = memory.set_interval(0, Rc::new(move || counter.set(counter.get() + 1)));
+
+        memory.advance(0);
+        assert_eq!(runs.get(), 0);
+        memory.advance(3);
+        assert_eq!(runs.get(), 3);
+
+        memory.cancel(interval);
+        memory.advance(3);
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't listen to click on NodeId(")]
+    fn names_the_event_when_listening_on_a_released_node() {
+        let memory = Memory::new();
+        let element = next_id();
+        memory.create_element(element, "p");
+        memory.release(element);
+        memory.add_listener(element, "click", Default::default(), Rc::new(|_| {}));
+    }
+}
@@ -0,0 +1,211 @@
+//! backend.rs describes the operations the virtual dom needs from a
+//! real dom. draw.rs and patch.rs only talk to the current backend, so
+//! the same trees can be rendered in a browser or fully in memory.
+
+mod batched;
+mod dom;
+mod memory;
+
+pub use batched::Batched;
+pub use dom::Dom;
+pub use memory::{Memory, MemoryEvent};
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+/// Refers to a node living in a backend. Ids are handed out on the rust
+/// side, so that patches can refer to nodes before they are created.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeId(pub(crate) u32);
+
+/// Refers to a timer, animation frame or global listener of a backend.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct CallbackId(pub(crate) u32);
+
+/// Where a global listener listens, outside of the tree.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Target {
+    Window,
+    Document,
+}
+
+/// How a listener listens. Backends honor all of these, also the ones
+/// a browser leaves to the listener itself.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ListenerOptions {
+    /// Listens while the event goes down to its target, before the
+    /// listeners it bubbles up to.
+    pub capture: bool,
+    /// Promises not to prevent the default, so the browser can scroll
+    /// right away.
+    pub passive: bool,
+    /// Removes the listener once it has been called.
+    pub once: bool,
+    pub prevent_default: bool,
+    pub stop_propagation: bool,
+}
+
+pub(crate) fn next_id() -> NodeId {
+    thread_local! {
+        static NEXT: Cell<u32> = const { Cell::new(0) };
+    }
+    NEXT.with(|next| {
+        let id = next.get();
+        next.set(id + 1);
+        NodeId(id)
+    })
+}
+
+/// The event a listener is called with.
+pub enum Event {
+    Dom(web_sys::Event),
+    Memory(MemoryEvent),
+}
+
+pub type Handler = Rc<dyn Fn(Event)>;
+
+pub trait Backend {
+    /// The node applications are attached to, like the document body.
+    fn root(&self) -> NodeId;
+    fn create_element(&self, id: NodeId, tag: &str);
+    fn create_text(&self, id: NodeId, data: &str);
+    fn set_attribute(&self, element: NodeId, name: &str, val: &str);
+    fn remove_attribute(&self, element: NodeId, name: &str);
+    fn set_text(&self, text: NodeId, data: &str);
+    /// Inserts `child` into `parent` before `before`, or at the end.
+    /// A child that is already attached somewhere is moved.
+    fn insert_child(&self, parent: NodeId, child: NodeId, before: Option<NodeId>);
+    fn replace_child(&self, child: NodeId, by: NodeId);
+    fn remove_child(&self, child: NodeId);
+    /// Forgets `node`, which has been taken out of the tree for good,
+    /// together with its listeners. Its children are released one by one,
+    /// and releasing a node twice does nothing.
+    fn release(&self, node: NodeId);
+    /// Sets the listener for `event` on `element`, replacing the previous one.
+    fn add_listener(
+        &self,
+        element: NodeId,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    );
+    fn remove_listener(&self, element: NodeId, event: &str);
+    /// Called once the patches of a frame have been applied, for backends
+    /// that queue their changes.
+    fn flush(&self) {}
+
+    // Callbacks that don't belong to a node, used by subscriptions.
+
+    /// Calls `handler` every `ms` milliseconds, until cancelled.
+    fn set_interval(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId;
+    /// Calls `handler` once, after `ms` milliseconds.
+    fn set_timeout(&self, ms: u32, handler: Rc<dyn Fn()>) -> CallbackId;
+    /// Calls `handler` once, with a timestamp in milliseconds, before the
+    /// next repaint.
+    fn request_animation_frame(&self, handler: Rc<dyn Fn(f64)>) -> CallbackId;
+    fn add_global_listener(
+        &self,
+        target: Target,
+        event: &str,
+        options: ListenerOptions,
+        handler: Handler,
+    ) -> CallbackId;
+    /// Stops an interval, timeout, animation frame or global listener.
+    /// Cancelling one that already ran or was cancelled does nothing.
+    fn cancel(&self, callback: CallbackId);
+
+    /// Called with the element an app is mounted into, where a delegating
+    /// backend listens to the events of that app.
+    fn mount(&self, _mount: NodeId) {}
+
+    /// The browser node behind `node`, for backends that have one.
+    fn web_node(&self, _node: NodeId) -> Option<web_sys::Node> {
+        None
+    }
+
+    // Reading back what is already there, used when hydrating.
+
+    fn element_by_id(&self, id: &str) -> Option<NodeId>;
+    /// The element and text children of `node`, skipping comments.
+    fn children(&self, node: NodeId) -> Vec<NodeId>;
+    /// The lowercase tag of an element, or `None` for a text.
+    fn tag(&self, node: NodeId) -> Option<String>;
+    /// The data of a text, or `None` for an element.
+    fn text(&self, node: NodeId) -> Option<String>;
+    fn attributes(&self, element: NodeId) -> Vec<(String, String)>;
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<dyn Backend>>> = const { RefCell::new(None) };
+}
+
+/// Makes `backend` the one every following draw and patch goes through.
+pub fn set_backend(backend: Rc<dyn Backend>) {
+    // the previous backend is dropped once replaced, as what it holds
+    // may look up the current one while dropped
+    let previous = CURRENT.with(|current| current.replace(Some(backend)));
+    drop(previous);
+}
+
+/// Returns the current backend, which is the browser dom on wasm
+/// and an in-memory dom everywhere else unless set otherwise.
+pub fn current() -> Rc<dyn Backend> {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .get_or_insert_with(|| {
+                if cfg!(target_arch = "wasm32") {
+                    Rc::new(Dom::new())
+                } else {
+                    Rc::new(Memory::new())
+                }
+            })
+            .clone()
+    })
+}
+
+/// The current backend, if there is one and the thread isn't shutting
+/// down, for destructors that clean up after themselves.
+pub(crate) fn try_current() -> Option<Rc<dyn Backend>> {
+    CURRENT
+        .try_with(|current| current.try_borrow().ok()?.clone())
+        .ok()
+        .flatten()
+}
+
+impl Event {
+    /// The value of the element the event was dispatched on.
+    pub fn value(&self) -> String {
+        match self {
+            Event::Dom(e) => dom::target_value(e),
+            Event::Memory(e) => e.value.clone().expect("This event doesn't have a value!"),
+        }
+    }
+
+    /// Whether the checkbox the event was dispatched on is checked.
+    pub fn checked(&self) -> bool {
+        match self {
+            Event::Dom(e) => dom::target_checked(e),
+            Event::Memory(e) => e.checked.expect("This event doesn't have a checked state!"),
+        }
+    }
+
+    /// The files picked with the file input the event was dispatched on.
+    pub fn files(&self) -> Vec<web_sys::File> {
+        match self {
+            Event::Dom(e) => dom::target_files(e),
+            Event::Memory(_) => panic!("Files can only be picked on the dom backend"),
+        }
+    }
+
+    pub fn dom_event(self) -> web_sys::Event {
+        match self {
+            Event::Dom(e) => e,
+            Event::Memory(_) => panic!("This listener needs a dom event, use the dom backend"),
+        }
+    }
+}
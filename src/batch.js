@@ -0,0 +1,133 @@
+// Applies the patches encoded by the batched backend (src/backend/batched.rs)
+// in one call. Nodes live here, in a map from their rust side ids.
+
+const CREATE_ELEMENT = 0;
+const CREATE_TEXT = 1;
+const SET_ATTRIBUTE = 2;
+const REMOVE_ATTRIBUTE = 3;
+const SET_TEXT = 4;
+const INSERT = 5;
+const REPLACE = 6;
+const REMOVE = 7;
+const LISTEN = 8;
+const UNLISTEN = 9;
+const RELEASE = 10;
+
+// the property elements keep their id in, for delegated events to find
+// their handlers (see Delegation in src/backend/dom.rs)
+const NODE_ID = "__lollipop";
+
+// the options of a listener, as bits
+const CAPTURE = 1;
+const PASSIVE = 2;
+const ONCE = 4;
+
+const nodes = new Map();
+// for every node, a map from event names to handlers and whether they capture
+const listeners = new Map();
+
+export function adopt(id, node) {
+    node[NODE_ID] = id;
+    nodes.set(id, node);
+}
+
+export function node(id) {
+    return nodes.get(id);
+}
+
+export function apply(ops, text, handlers) {
+    let i = 0;
+    const id = () => ops[i++];
+    const str = () => {
+        const start = ops[i++];
+        const len = ops[i++];
+        return text.substring(start, start + len);
+    };
+
+    while (i < ops.length) {
+        switch (ops[i++]) {
+            case CREATE_ELEMENT: {
+                const node = id();
+                const element = document.createElement(str());
+                element[NODE_ID] = node;
+                nodes.set(node, element);
+                break;
+            }
+            case CREATE_TEXT: {
+                const node = id();
+                nodes.set(node, document.createTextNode(str()));
+                break;
+            }
+            case SET_ATTRIBUTE: {
+                const node = nodes.get(id());
+                const name = str();
+                node.setAttribute(name, str());
+                break;
+            }
+            case REMOVE_ATTRIBUTE:
+                nodes.get(id()).removeAttribute(str());
+                break;
+            case SET_TEXT: {
+                const node = nodes.get(id());
+                node.data = str();
+                break;
+            }
+            case INSERT: {
+                const parent = nodes.get(id());
+                const child = nodes.get(id());
+                // before is shifted by one, zero meaning at the end
+                const before = id();
+                parent.insertBefore(child, before === 0 ? null : nodes.get(before - 1));
+                break;
+            }
+            case REPLACE: {
+                const old = nodes.get(id());
+                old.replaceWith(nodes.get(id()));
+                break;
+            }
+            case REMOVE:
+                nodes.get(id()).remove();
+                break;
+            case LISTEN: {
+                const node = id();
+                const event = str();
+                const handler = handlers[id()];
+                const options = id();
+                const capture = (options & CAPTURE) !== 0;
+                unlisten(node, event);
+                nodes.get(node).addEventListener(event, handler, {
+                    capture,
+                    passive: (options & PASSIVE) !== 0,
+                    once: (options & ONCE) !== 0,
+                });
+                if (!listeners.has(node)) {
+                    listeners.set(node, new Map());
+                }
+                listeners.get(node).set(event, { handler, capture });
+                break;
+            }
+            case UNLISTEN: {
+                const node = id();
+                unlisten(node, str());
+                break;
+            }
+            case RELEASE: {
+                const node = id();
+                for (const event of listeners.get(node)?.keys() ?? []) {
+                    unlisten(node, event);
+                }
+                listeners.delete(node);
+                nodes.delete(node);
+                break;
+            }
+        }
+    }
+}
+
+function unlisten(node, event) {
+    const listener = listeners.get(node)?.get(event);
+    if (listener !== undefined) {
+        nodes.get(node).removeEventListener(event, listener.handler, listener.capture);
+        listeners.get(node).delete(event);
+    }
+}
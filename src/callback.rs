@@ -0,0 +1,146 @@
+//! callback.rs lets a widget report back to the widget whose view it is
+//! in. The parent makes a callback in its view and passes it down in the
+//! props, and the child emits values through it, which arrive at the
+//! parent as its own messages.
+
+use std::{any::TypeId, fmt, rc::Rc};
+
+use crate::{
+    console_log,
+    runtime::{Runtime, WeakRuntime},
+};
+
+/// Sends values of type `T` to the widget that made the callback, as
+/// messages of its own type.
+pub struct Callback<T> {
+    /// The runtime of the widget, which is weak as the callback ends up
+    /// in the props the runtime keeps.
+    runtime: WeakRuntime,
+    /// The widget instance to send to.
+    wid: String,
+    /// The address of the function making the messages, which tells
+    /// callbacks apart when props are compared.
+    addr: *const (),
+    send: Rc<Sender<T>>,
+}
+
+/// Sends a value to a widget instance in a runtime.
+type Sender<T> = dyn Fn(&Runtime, &str, T);
+
+impl<T: 'static> Callback<T> {
+    /// Makes a callback to the widget whose view is running, which turns
+    /// every value into one of its messages with `msg`.
+    pub fn new<M: 'static>(msg: fn(T) -> M) -> Self {
+        let runtime = Runtime::current();
+        let (wid, message_type) = runtime
+            .viewing()
+            .expect("Callbacks can only be made in the view of a widget");
+        if message_type != TypeId::of::<M>() {
+            panic!(
+                "The callback made in the view of widget {} sends messages of another type",
+                wid
+            );
+        }
+        Self {
+            runtime: runtime.downgrade(),
+            wid,
+            addr: msg as *const (),
+            send: Rc::new(move |runtime, wid, value| runtime.update_widget(wid, msg(value))),
+        }
+    }
+
+    /// Sends `value` to the widget that made the callback. Like any
+    /// message, it is handled once the current one is done. It can be
+    /// called from anywhere, like a task, as long as the app is around.
+    pub fn emit(&self, value: T) {
+        match self.runtime.upgrade() {
+            Some(runtime) => (self.send)(&runtime, &self.wid, value),
+            None => console_log!("[callback] dropped a value to stopped app {}", self.wid),
+        }
+    }
+}
+
+impl<T> Clone for Callback<T> {
+    fn clone(&self) -> Self {
+        Self {
+            runtime: self.runtime.clone(),
+            wid: self.wid.clone(),
+            addr: self.addr,
+            send: self.send.clone(),
+        }
+    }
+}
+
+/// Callbacks are equal when they send to the same widget with the same
+/// function, so passing one down doesn't make the child render again.
+impl<T> PartialEq for Callback<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.runtime.ptr_eq(&other.runtime) && self.wid == other.wid && self.addr == other.addr
+    }
+}
+
+impl<T> fmt::Debug for Callback<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callback").field("wid", &self.wid).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use wasm_rs_async_executor::single_threaded as executor;
+
+    use crate::api::*;
+
+    fn parent() -> Widget {
+        Widget::new(
+            "parent",
+            (),
+            || 0,
+            |got: &mut u32, _: &(), n: u32| {
+                *got = n;
+                Action::Diff
+            },
+            |got: &u32, _: &()| -> Html<u32> {
+                div()
+                    .child(got.to_string())
+                    .child(child(Callback::new(|n| n)))
+            },
+        )
+    }
+
+    /// Emits 7 from a task once clicked.
+    fn child(emit: Callback<u32>) -> Widget {
+        Widget::new(
+            "child",
+            emit,
+            || (),
+            |_: &mut (), emit: &Callback<u32>, clicked: bool| {
+                let emit = emit.clone();
+                match clicked {
+                    true => async move {
+                        emit.emit(7);
+                        false
+                    }
+                    .into(),
+                    false => Action::Nothing,
+                }
+            },
+            |_: &(), _: &Callback<u32>| -> Html<bool> { button().on("click", || true) },
+        )
+    }
+
+    #[test]
+    fn emits_from_a_task() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(parent);
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch(memory.children(el)[1], "click", MemoryEvent::default());
+        executor::run(None);
+        runtime.flush();
+        assert_eq!(memory.html(el), "<div>7<button></button></div>");
+    }
+}
@@ -1,37 +1,64 @@
 //! diff.rs finds the differences between the old
-//! and the new virtual dom, and updates the real dom
-//! to these changes.
+//! and the new virtual dom, and lists the patches that
+//! update the real dom to these changes.
+
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::{
-    console_log, draw,
-    events::ListenerKind::{self, Evented, Factory, Valued},
+    backend::NodeId,
+    draw,
+    events::Listener,
     nodes::{element::Element, node::Node, text::Text, widget::Widget},
-    patch,
+    patch::{ListenerId, Patch},
 };
 
-pub fn diff_node<M>(wname: &str, from: &mut Node<M>, to: &mut Node<M>) {
+/// Lists the patches that turn the drawn `from` into `to`, handing the
+/// dom nodes that are kept over to `to`. New listeners send their
+/// messages to the widget instance `wid`.
+pub fn diff<M>(wid: &str, from: &mut Node<M>, to: &mut Node<M>) -> Vec<Patch> {
+    let mut patches = Vec::new();
+    diff_node(wid, from, to, &mut patches);
+    patches
+}
+
+pub fn diff_node<M>(wid: &str, from: &mut Node<M>, to: &mut Node<M>, patches: &mut Vec<Patch>) {
     match (from, to) {
-        (Node::Element(from), Node::Element(to)) => diff_element(wname, from, to),
-        (Node::Text(from), Node::Text(to)) => diff_text(from, to),
-        (Node::Widget(from), Node::Widget(to)) => diff_widget(from, to),
+        (Node::Element(from), Node::Element(to)) => diff_element(wid, from, to, patches),
+        (Node::Text(from), Node::Text(to)) => diff_text(from, to, patches),
+        (Node::Widget(from), Node::Widget(to)) => diff_widget(from, to, patches),
         (from, to) => {
-            draw::node(to);
-            patch::replace_node(from.dom_node().unwrap(), to.dom_node().unwrap())
+            draw::node(wid, to, patches);
+            patches.push(Patch::Replace {
+                old: from.dom_node().unwrap(),
+                new: to.dom_node().unwrap(),
+            });
+            release(from, patches);
         }
     }
 }
 
-pub fn diff_widget(from: &Widget, to: &Widget) {
-    if from.name != to.name {
-        patch::replace_node(&from.dom_node, &to.dom_node);
+pub fn diff_widget(from: &mut Widget, to: &mut Widget, patches: &mut Vec<Patch>) {
+    if !to.take_over(from, patches) {
+        to.mount(patches);
+        // unwrapping is safe because both widgets have been mounted
+        patches.push(Patch::Replace {
+            old: from.dom_node().unwrap(),
+            new: to.dom_node().unwrap(),
+        });
+        if let Some(id) = from.id.take() {
+            patches.push(Patch::Unmount { widget: id });
+        }
     }
 }
 
-pub fn diff_text(from: &mut Text, to: &mut Text) {
+pub fn diff_text(from: &mut Text, to: &mut Text, patches: &mut Vec<Patch>) {
     match from.dom_text_node.take() {
         Some(text_node) => {
             if from.val != to.val {
-                patch::set_text_data(&text_node, &to.val);
+                patches.push(Patch::SetText {
+                    id: text_node,
+                    data: to.val.clone(),
+                });
             }
             to.dom_text_node = Some(text_node);
         }
@@ -39,109 +66,726 @@ pub fn diff_text(from: &mut Text, to: &mut Text) {
     }
 }
 
-pub fn diff_element<M>(wname: &str, from: &mut Element<M>, to: &mut Element<M>) {
+pub fn diff_element<M>(
+    wid: &str,
+    from: &mut Element<M>,
+    to: &mut Element<M>,
+    patches: &mut Vec<Patch>,
+) {
     match from.dom_element.take() {
         Some(dom_element) => {
-            if diff_name(&dom_element, from, to) {
-                diff_attributes(&dom_element, from, to);
-                diff_children(wname, &dom_element, from, to);
-                diff_listeners(wname, &dom_element, from, to)
+            if diff_name(wid, dom_element, from, to, patches) {
+                diff_attributes(dom_element, from, to, patches);
+                diff_children(wid, dom_element, from, to, patches);
+                diff_listeners(wid, dom_element, from, to, patches);
+                to.dom_element = Some(dom_element);
             }
-            to.dom_element = Some(dom_element);
         }
         None => todo!(),
     }
 }
 
-fn diff_name<M>(dom_element: &web_sys::Element, from: &Element<M>, to: &mut Element<M>) -> bool {
+fn diff_name<M>(
+    wid: &str,
+    dom_element: NodeId,
+    from: &Element<M>,
+    to: &mut Element<M>,
+    patches: &mut Vec<Patch>,
+) -> bool {
     if from.tag != to.tag {
-        draw::element(to);
+        draw::element(wid, to, patches);
 
         // unwrapping is safe because 'draw::element()' must have provided
-        patch::replace_node(&dom_element, to.dom_element.as_ref().unwrap());
+        patches.push(Patch::Replace {
+            old: dom_element,
+            new: to.dom_element.unwrap(),
+        });
+        release_element(dom_element, from, patches);
         false
     } else {
         true
     }
 }
 
-fn diff_attributes<M>(dom_element: &web_sys::Element, from: &Element<M>, to: &Element<M>) {
+fn diff_attributes<M>(
+    dom_element: NodeId,
+    from: &Element<M>,
+    to: &Element<M>,
+    patches: &mut Vec<Patch>,
+) {
     for (from_name, from_val) in &from.attrs {
         match to.attrs.get(from_name) {
             Some(to_val) => {
                 if from_val != to_val {
-                    patch::set_attribute(dom_element, from_name, to_val);
+                    patches.push(Patch::SetAttribute {
+                        id: dom_element,
+                        name: from_name,
+                        val: to_val.clone(),
+                    });
                 }
             }
-            None => patch::remove_attribute(dom_element, from_name),
+            None => patches.push(Patch::RemoveAttribute {
+                id: dom_element,
+                name: from_name,
+            }),
         }
     }
     for (to_name, to_val) in &to.attrs {
         if !from.attrs.contains_key(to_name) {
-            patch::set_attribute(dom_element, to_name, to_val)
+            patches.push(Patch::SetAttribute {
+                id: dom_element,
+                name: to_name,
+                val: to_val.clone(),
+            })
         }
     }
 }
 
 fn diff_children<M>(
-    wname: &str,
-    dom_element: &web_sys::Element,
+    wid: &str,
+    dom_element: NodeId,
     from: &mut Element<M>,
     to: &mut Element<M>,
+    patches: &mut Vec<Patch>,
 ) {
+    let keyed = |children: &[Node<M>]| children.iter().any(|child| child.key().is_some());
+    if keyed(&from.children) || keyed(&to.children) {
+        check_keys(to);
+        return diff_keyed_children(wid, dom_element, from, to, patches);
+    }
+
     let from_len = from.children.len();
     let to_len = to.children.len();
 
     if from_len >= to_len {
-        patch::remove_children(dom_element, from_len - to_len)
+        for child in from.children[to_len..].iter() {
+            // unwrapping is safe because the old tree has been drawn
+            patches.push(Patch::Remove {
+                id: child.dom_node().unwrap(),
+            });
+            release(child, patches);
+        }
     } else {
-        patch::add_children(
-            dom_element,
-            to.children[from_len..].iter_mut().map(|child| {
-                draw::node(child);
+        for child in to.children[from_len..].iter_mut() {
+            draw::node(wid, child, patches);
 
-                // unwrapping is safe because 'draw::element()' must have provided
-                child.dom_node().unwrap()
-            }),
-        )
+            // unwrapping is safe because 'draw::node()' must have provided
+            patches.push(Patch::Insert {
+                parent: dom_element,
+                child: child.dom_node().unwrap(),
+                before: None,
+            });
+        }
     }
     for i in 0..std::cmp::min(from_len, to_len) {
-        diff_node(wname, &mut from.children[i], &mut to.children[i]);
+        diff_node(wid, &mut from.children[i], &mut to.children[i], patches);
     }
 }
 
-fn diff_listeners<M>(
-    wname: &str,
-    dom_element: &web_sys::Element,
+/// Lists the patches that tear down `node` once it has left the tree for
+/// good: the widgets in it are unmounted and its dom nodes are released,
+/// children first.
+pub(crate) fn release<M>(node: &Node<M>, patches: &mut Vec<Patch>) {
+    match node {
+        Node::Element(el) => {
+            if let Some(dom_element) = el.dom_element {
+                release_element(dom_element, el, patches)
+            }
+        }
+        Node::Text(txt) => {
+            if let Some(id) = txt.dom_text_node {
+                patches.push(Patch::Release { id })
+            }
+        }
+        Node::Widget(widget) => {
+            if let Some(id) = &widget.id {
+                patches.push(Patch::Unmount { widget: id.clone() })
+            }
+        }
+    }
+}
+
+fn release_element<M>(dom_element: NodeId, el: &Element<M>, patches: &mut Vec<Patch>) {
+    for child in el.children.iter() {
+        release(child, patches);
+    }
+    patches.push(Patch::Release { id: dom_element });
+}
+
+/// Panics when two children of `element` have the same key, as it would be
+/// unclear which of them the old child with that key became.
+pub(crate) fn check_keys<M>(element: &Element<M>) {
+    let mut seen = HashSet::new();
+    for child in element.children.iter() {
+        if let Some(key) = child.key() {
+            if !seen.insert(key) {
+                match child {
+                    Node::Widget(widget) => panic!(
+                        "Two {} widgets in <{}> have the key {:?}",
+                        widget.name, element.tag, key
+                    ),
+                    _ => panic!("Two children of <{}> have the key {:?}", element.tag, key),
+                }
+            }
+        }
+    }
+}
+
+/// Matches the new children to the old ones by key (unkeyed children are
+/// matched in order among themselves), diffs every matched pair, and then
+/// moves only the dom nodes that are not part of the longest run that
+/// already is in the right order.
+fn diff_keyed_children<M>(
+    wid: &str,
+    dom_element: NodeId,
     from: &mut Element<M>,
     to: &mut Element<M>,
+    patches: &mut Vec<Patch>,
+) {
+    let mut keyed = HashMap::new();
+    let mut unkeyed = VecDeque::new();
+    for (i, child) in from.children.iter().enumerate() {
+        match child.key() {
+            Some(key) => {
+                keyed.entry(key.to_string()).or_insert(i);
+            }
+            None => unkeyed.push_back(i),
+        }
+    }
+
+    // for every new child, the index of the old child it reuses
+    let mut sources: Vec<Option<usize>> = Vec::with_capacity(to.children.len());
+    let mut matched = vec![false; from.children.len()];
+    for child in to.children.iter() {
+        let source = match child.key() {
+            Some(key) => keyed.remove(key),
+            None => unkeyed.pop_front(),
+        };
+        if let Some(i) = source {
+            matched[i] = true;
+        }
+        sources.push(source);
+    }
+
+    for (i, child) in from.children.iter().enumerate() {
+        if !matched[i] {
+            // unwrapping is safe because the old tree has been drawn
+            patches.push(Patch::Remove {
+                id: child.dom_node().unwrap(),
+            });
+            release(child, patches);
+        }
+    }
+
+    for (child, source) in to.children.iter_mut().zip(sources.iter()) {
+        match source {
+            Some(i) => diff_node(wid, &mut from.children[*i], child, patches),
+            None => draw::node(wid, child, patches),
+        }
+    }
+
+    let reused: Vec<usize> = sources.iter().flatten().copied().collect();
+    let mut stays = vec![false; to.children.len()];
+    let positions: Vec<usize> = (0..to.children.len())
+        .filter(|&j| sources[j].is_some())
+        .collect();
+    for i in longest_increasing_subsequence(&reused) {
+        stays[positions[i]] = true;
+    }
+
+    let mut next: Option<NodeId> = None;
+    for (j, child) in to.children.iter().enumerate().rev() {
+        // unwrapping is safe because every new child was either diffed or drawn
+        let dom_node = child.dom_node().unwrap();
+        if !stays[j] {
+            patches.push(Patch::Insert {
+                parent: dom_element,
+                child: dom_node,
+                before: next,
+            });
+        }
+        next = Some(dom_node);
+    }
+}
+
+/// Returns the positions in `seq` of one of its longest strictly increasing
+/// subsequences.
+fn longest_increasing_subsequence(seq: &[usize]) -> Vec<usize> {
+    // tails[k] is the position of the smallest tail of an increasing
+    // subsequence of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; seq.len()];
+    for (i, &x) in seq.iter().enumerate() {
+        let k = tails.partition_point(|&t| seq[t] < x);
+        if k > 0 {
+            prev[i] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut lis = Vec::with_capacity(tails.len());
+    let mut cursor = tails.last().copied();
+    while let Some(i) = cursor {
+        lis.push(i);
+        cursor = prev[i];
+    }
+    lis.reverse();
+    lis
+}
+
+fn diff_listeners<M>(
+    wid: &str,
+    dom_element: NodeId,
+    from: &Element<M>,
+    to: &Element<M>,
+    patches: &mut Vec<Patch>,
 ) {
     for (from_name, from_val) in from.listeners.iter() {
-        match to.listeners.get_mut(from_name) {
+        match to.listeners.get(from_name) {
             Some(to_val) => {
-                if listener_are_different(&from_val.kind, &to_val.kind) {
-                    console_log!("Listeners are different!");
-                    patch::set_listener(wname, dom_element, from_name, to_val);
+                if listener_are_different(from_val, to_val) {
+                    patches.push(Patch::SetListener {
+                        id: dom_element,
+                        event: from_name,
+                        options: to_val.options,
+                        listener: ListenerId::new(wid, to_val),
+                    });
                 }
             }
-            None => {
-                patch::remove_listener(dom_element, from_name, from_val);
-            }
+            None => patches.push(Patch::RemoveListener {
+                id: dom_element,
+                event: from_name,
+            }),
         }
     }
-    for (to_name, to_val) in to.listeners.iter_mut() {
+    for (to_name, to_val) in to.listeners.iter() {
         if !from.listeners.contains_key(to_name) {
-            patch::set_listener(wname, dom_element, to_name, to_val)
+            patches.push(Patch::SetListener {
+                id: dom_element,
+                event: to_name,
+                options: to_val.options,
+                listener: ListenerId::new(wid, to_val),
+            })
         }
     }
 }
 
-pub fn listener_are_different<M>(l1: &ListenerKind<M>, l2: &ListenerKind<M>) -> bool {
-    match (l1, l2) {
-        (Factory(fac1), Factory(fac2)) => fac1 != fac2, 
-        (Evented(cb1), Evented(cb2)) => cb1 != cb2,
-        (Valued(cb1), Valued(cb2)) => cb1 != cb2, 
-        (_, _) => true,
+pub fn listener_are_different<M>(l1: &Listener<M>, l2: &Listener<M>) -> bool {
+    l1 != l2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::diff;
+    use crate::{api::*, draw::draw, patch};
+
+    /// Draws `el` without applying the patches, which gives it dom ids.
+    fn ids(el: Html<u8>) -> Node<u8> {
+        let mut node = el.node();
+        draw("test", &mut node);
+        node
+    }
+
+    fn one() -> u8 {
+        1
+    }
+
+    fn two() -> u8 {
+        2
+    }
+
+    /// Draws `el` into the memory backend, in a runtime for its listeners.
+    fn drawn(runtime: &Runtime, el: Html<()>) -> Node<()> {
+        let mut node = el.node();
+        runtime.enter(|| patch::apply(draw("test", &mut node)));
+        node
+    }
+
+    fn list<M>(keys: &[&str]) -> Html<M> {
+        let items = keys.iter().map(|key| p().key(key).child(*key));
+        items.fold(div(), |list, item| list.child(item))
+    }
+
+    #[test]
+    fn diffs_to_what_drawing_makes() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = Runtime::new();
+        let view = |n: u32| {
+            let el = div().attr("n", n).child(n.to_string());
+            match n {
+                0 => el.child(p().on("click", || ())).child("zero"),
+                _ => el.child(h1().child("one")),
+            }
+        };
+
+        let mut old = drawn(&runtime, view(0));
+        let mut new = view(1).node();
+        runtime.enter(|| patch::apply(diff("test", &mut old, &mut new)));
+        let fresh = drawn(&runtime, view(1));
+        assert_eq!(
+            memory.html(new.dom_node().unwrap()),
+            "<div n=\"1\">1<h1>one</h1></div>"
+        );
+        assert_eq!(
+            memory.html(new.dom_node().unwrap()),
+            memory.html(fresh.dom_node().unwrap())
+        );
+    }
+
+    #[test]
+    fn moves_only_the_keyed_children_out_of_order() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = Runtime::new();
+        let mut old = drawn(&runtime, list(&["a", "b", "c"]));
+        let old_ids: Vec<_> = old.element().children.iter().map(Node::dom_node).collect();
+
+        let mut new = list(&["c", "a", "b"]).node();
+        let patches = diff("test", &mut old, &mut new);
+        assert_eq!(
+            patches,
+            vec![Patch::Insert {
+                parent: new.dom_node().unwrap(),
+                child: old_ids[2].unwrap(),
+                before: old_ids[0],
+            }]
+        );
+
+        runtime.enter(|| patch::apply(patches));
+        let new_ids: Vec<_> = new.element().children.iter().map(Node::dom_node).collect();
+        assert_eq!(new_ids, vec![old_ids[2], old_ids[0], old_ids[1]]);
+        assert_eq!(
+            memory.html(new.dom_node().unwrap()),
+            "<div><p>c</p><p>a</p><p>b</p></div>"
+        );
+    }
+
+    /// Shows `text`, which comes in as its props.
+    fn shown(text: &'static str) -> Widget {
+        Widget::new(
+            "shown",
+            text,
+            || (),
+            |_: &mut (), _: &&'static str, _: ()| Action::Nothing,
+            |_: &(), text: &&'static str| -> Html<()> { p().child(*text) },
+        )
+    }
+
+    #[test]
+    fn lists_the_patches_of_child_widgets_without_applying_them() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = Runtime::new();
+
+        let mut old: Node<()> = div().child(shown("a")).node();
+        let patches = runtime.enter(|| draw("test", &mut old));
+        assert_eq!(memory.node_count(), 0);
+        runtime.enter(|| patch::apply(patches));
+        let el = old.dom_node().unwrap();
+        assert_eq!(memory.html(el), "<div><p>a</p></div>");
+
+        let mut new: Node<()> = div().child(shown("b")).node();
+        let patches = runtime.enter(|| diff("test", &mut old, &mut new));
+        assert_eq!(memory.html(el), "<div><p>a</p></div>");
+        assert!(matches!(
+            patches.as_slice(),
+            [Patch::SetText { data, .. }] if data == "b"
+        ));
+        runtime.enter(|| patch::apply(patches));
+        assert_eq!(memory.html(el), "<div><p>b</p></div>");
+    }
+
+    #[test]
+    fn replaces_an_element_of_another_tag() {
+        let mut old = ids(div().child("x"));
+        let (old_el, old_text) = (old.dom_node(), old.element().children[0].dom_node());
+        let mut new = p().child("x").node();
+        let patches = diff("test", &mut old, &mut new);
+
+        let (new_el, new_text) = (new.dom_node(), new.element().children[0].dom_node());
+        assert_eq!(
+            patches,
+            vec![
+                Patch::CreateElement {
+                    id: new_el.unwrap(),
+                    tag: "p",
+                },
+                Patch::CreateText {
+                    id: new_text.unwrap(),
+                    data: "x".to_string(),
+                },
+                Patch::Insert {
+                    parent: new_el.unwrap(),
+                    child: new_text.unwrap(),
+                    before: None,
+                },
+                Patch::Replace {
+                    old: old_el.unwrap(),
+                    new: new_el.unwrap(),
+                },
+                Patch::Release {
+                    id: old_text.unwrap(),
+                },
+                Patch::Release {
+                    id: old_el.unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_attributes_and_texts_in_place() {
+        let mut old = ids(div().attr("class", "a").child("1"));
+        let (el, text) = (
+            old.dom_node().unwrap(),
+            old.element().children[0].dom_node(),
+        );
+        let mut new = div().attr("class", "b").child("2").node();
+        assert_eq!(
+            diff("test", &mut old, &mut new),
+            vec![
+                Patch::SetAttribute {
+                    id: el,
+                    name: "class",
+                    val: "b".to_string(),
+                },
+                Patch::SetText {
+                    id: text.unwrap(),
+                    data: "2".to_string(),
+                },
+            ]
+        );
+
+        let mut newer = div().attr("title", "t").child("2").node();
+        assert_eq!(
+            diff("test", &mut new, &mut newer),
+            vec![
+                Patch::RemoveAttribute {
+                    id: el,
+                    name: "class",
+                },
+                Patch::SetAttribute {
+                    id: el,
+                    name: "title",
+                    val: "t".to_string(),
+                },
+            ]
+        );
+        assert_eq!(newer.dom_node(), Some(el));
+    }
+
+    #[test]
+    fn inserts_and_removes_keyed_children() {
+        let mut old = ids(list(&["a", "b", "c"]));
+        let old_ids: Vec<_> = old
+            .element()
+            .children
+            .iter_mut()
+            .map(|item| {
+                (
+                    item.dom_node().unwrap(),
+                    item.element().children[0].dom_node(),
+                )
+            })
+            .collect();
+        let mut new = list(&["b", "d"]).node();
+        let patches = diff("test", &mut old, &mut new);
+
+        let d = &mut new.element().children[1];
+        let (d_el, d_text) = (d.dom_node().unwrap(), d.element().children[0].dom_node());
+        let removed = |(el, text): (NodeId, Option<NodeId>)| {
+            vec![
+                Patch::Remove { id: el },
+                Patch::Release { id: text.unwrap() },
+                Patch::Release { id: el },
+            ]
+        };
+        let mut expected = removed(old_ids[0]);
+        expected.extend(removed(old_ids[2]));
+        expected.extend([
+            Patch::CreateElement { id: d_el, tag: "p" },
+            Patch::CreateText {
+                id: d_text.unwrap(),
+                data: "d".to_string(),
+            },
+            Patch::Insert {
+                parent: d_el,
+                child: d_text.unwrap(),
+                before: None,
+            },
+            Patch::Insert {
+                parent: new.dom_node().unwrap(),
+                child: d_el,
+                before: None,
+            },
+        ]);
+        assert_eq!(patches, expected);
+    }
+
+    #[test]
+    fn sets_only_the_listeners_that_changed() {
+        let mut old = ids(div().on("click", one));
+        let el = old.dom_node().unwrap();
+
+        let mut same = div().on("click", one).node();
+        assert_eq!(diff("test", &mut old, &mut same), vec![]);
+
+        let mut other = div().on("click", two).node();
+        assert!(matches!(
+            diff("test", &mut same, &mut other).as_slice(),
+            [Patch::SetListener {
+                id,
+                event: "click",
+                options,
+                ..
+            }] if *id == el && *options == ListenerOptions::default()
+        ));
+
+        let mut once = div().on("click", two).once().node();
+        assert!(matches!(
+            diff("test", &mut other, &mut once).as_slice(),
+            [Patch::SetListener {
+                id,
+                event: "click",
+                options: ListenerOptions { once: true, .. },
+                ..
+            }] if *id == el
+        ));
+
+        let mut none = div().node();
+        assert_eq!(
+            diff("test", &mut once, &mut none),
+            vec![Patch::RemoveListener {
+                id: el,
+                event: "click"
+            }]
+        );
+    }
+
+    #[test]
+    fn binds_closures_again_only_when_their_deps_change() {
+        let deleted = |n: u8| div().on_with("click", n, move || n);
+        let mut old = ids(deleted(1));
+        let el = old.dom_node().unwrap();
+
+        let mut same = deleted(1).node();
+        assert_eq!(diff("test", &mut old, &mut same), vec![]);
+
+        let mut other = deleted(2).node();
+        assert!(matches!(
+            diff("test", &mut same, &mut other).as_slice(),
+            [Patch::SetListener { id, event: "click", .. }] if *id == el
+        ));
+    }
+
+    fn clicked(_: web_sys::MouseEvent) -> u8 {
+        1
+    }
+
+    fn pressed(_: web_sys::KeyboardEvent) -> u8 {
+        2
+    }
+
+    #[test]
+    fn sets_typed_listeners_again_only_when_their_function_changes() {
+        let mut old = ids(div().on_click(clicked));
+        let el = old.dom_node().unwrap();
+
+        let mut same = div().on_click(clicked).node();
+        assert_eq!(diff("test", &mut old, &mut same), vec![]);
+
+        let mut both = div().on_click(clicked).on_keydown(pressed).node();
+        assert!(matches!(
+            diff("test", &mut same, &mut both).as_slice(),
+            [Patch::SetListener { id, event: "keydown", .. }] if *id == el
+        ));
+
+        let mut other = div().on_click(|_| 3).on_keydown(pressed).node();
+        assert!(matches!(
+            diff("test", &mut both, &mut other).as_slice(),
+            [Patch::SetListener { id, event: "click", .. }] if *id == el
+        ));
+    }
+
+    fn escaped(e: web_sys::KeyboardEvent) -> Option<u8> {
+        (e.key() == "Escape").then_some(1)
+    }
+
+    fn entered(_: web_sys::Event) -> Option<u8> {
+        None
+    }
+
+    #[test]
+    fn sets_maybe_listeners_again_only_when_their_function_changes() {
+        let mut old = ids(input()
+            .on_keydown_maybe(escaped)
+            .on_event_maybe("input", entered));
+        let el = old.dom_node().unwrap();
+
+        let mut same = input()
+            .on_keydown_maybe(escaped)
+            .on_event_maybe("input", entered)
+            .node();
+        assert_eq!(diff("test", &mut old, &mut same), vec![]);
+
+        let mut other = input()
+            .on_keydown_maybe(|_| None)
+            .on_event_maybe("input", entered)
+            .node();
+        assert!(matches!(
+            diff("test", &mut same, &mut other).as_slice(),
+            [Patch::SetListener { id, event: "keydown", .. }] if *id == el
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "This listener needs a dom event")]
+    fn maybe_listeners_need_a_dom_event() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = Runtime::new();
+        let el = drawn(&runtime, p().on_event_maybe("click", |_| Some(())));
+        memory.dispatch(el.dom_node().unwrap(), "click", MemoryEvent::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "This listener needs a dom event")]
+    fn typed_listeners_need_a_dom_event() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = Runtime::new();
+        let el = drawn(&runtime, p().on_click(|_| ()));
+        memory.dispatch(el.dom_node().unwrap(), "click", MemoryEvent::default());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_patches_with_listeners_by_id() {
+        let mut node = p().on("click", one).node();
+        let patches = draw("test", &mut node);
+        let Patch::SetListener { listener, .. } = &patches[1] else {
+            panic!("Expected the listener after the element, got {:?}", patches);
+        };
+        let el = node.dom_node().unwrap();
+        assert_eq!(
+            serde_json::to_string(&patches).unwrap(),
+            format!(
+                concat!(
+                    r#"[{{"CreateElement":{{"id":{},"tag":"p"}}}},"#,
+                    r#"{{"SetListener":{{"id":{},"event":"click","options":{{"capture":false,"#,
+                    r#""passive":false,"once":false,"prevent_default":false,"#,
+                    r#""stop_propagation":false}},"listener":{}}}}}]"#
+                ),
+                el.0, el.0, listener.0
+            )
+        );
     }
 }
 
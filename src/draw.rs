@@ -1,75 +1,95 @@
 //! draw.rs handles rendering a virtual dom element to a real dom node.
-//! basically, it fills in the dom node or dom element for every node
+//! basically, it fills in the dom node or dom element for every node,
+//! and lists the patches that create them
 
 use crate::{
+    backend::{self, NodeId},
+    diff,
     nodes::{element::Element, node::Node, text::Text},
-    utils::document,
+    patch::{ListenerId, Patch},
 };
 
-pub fn node<M>(node: &mut Node<M>) {
+/// Lists the patches that create `node` from scratch. Its listeners send
+/// their messages to the widget instance `wid`.
+pub fn draw<M>(wid: &str, node: &mut Node<M>) -> Vec<Patch> {
+    let mut patches = Vec::new();
+    self::node(wid, node, &mut patches);
+    patches
+}
+
+pub fn node<M>(wid: &str, node: &mut Node<M>, patches: &mut Vec<Patch>) {
     match node {
-        Node::Element(el) => element(el),
-        Node::Text(txt) => text(txt),
-        Node::Widget(_) => {
-            // widget is already drawn when created
-        }
+        Node::Element(el) => element(wid, el, patches),
+        Node::Text(txt) => text(txt, patches),
+        Node::Widget(widget) => widget.mount(patches),
     }
 }
 
-pub fn text(text: &mut Text) {
-    let dom_text_node = document().create_text_node(&text.val);
+pub fn text(text: &mut Text, patches: &mut Vec<Patch>) {
+    let dom_text_node = backend::next_id();
+    patches.push(Patch::CreateText {
+        id: dom_text_node,
+        data: text.val.clone(),
+    });
     text.dom_text_node = Some(dom_text_node);
 }
 
-pub fn element<M>(element: &mut Element<M>) {
-    let dom_element = create_dom_element(
-        element.tag,
-        element.attrs.iter_mut().map(|(name, val)| (*name, &**val)),
-        element.children.iter_mut(),
-    );
-    element.dom_element = Some(dom_element);
-}
-
-fn create_dom_element<'a, M: 'static>(
-    name: &str,
-    attrs: impl Iterator<Item = (&'a str, &'a str)>,
-    children: impl Iterator<Item = &'a mut Node<M>>,
-) -> web_sys::Element {
-    let dom_element = match document().create_element(name) {
-        Ok(dom_element) => dom_element,
-        Err(_) => todo!(),
-    };
+pub fn element<M>(wid: &str, element: &mut Element<M>, patches: &mut Vec<Patch>) {
+    let dom_element = backend::next_id();
+    patches.push(Patch::CreateElement {
+        id: dom_element,
+        tag: element.tag,
+    });
 
-    set_attributes(&dom_element, attrs);
-    add_children(&dom_element, children);
+    diff::check_keys(element);
+    set_attributes(dom_element, element, patches);
+    add_children(wid, dom_element, element.children.iter_mut(), patches);
+    add_listeners(wid, dom_element, element, patches);
 
-    return dom_element;
+    element.dom_element = Some(dom_element);
 }
 
-fn set_attributes<'a>(
-    dom_element: &web_sys::Element,
-    attrs: impl Iterator<Item = (&'a str, &'a str)>,
-) {
-    for (name, val) in attrs {
-        match dom_element.set_attribute(name, val) {
-            Ok(()) => (),
-            Err(_) => todo!(),
-        }
+fn set_attributes<M>(dom_element: NodeId, element: &Element<M>, patches: &mut Vec<Patch>) {
+    for (name, val) in element.attrs.iter() {
+        patches.push(Patch::SetAttribute {
+            id: dom_element,
+            name,
+            val: val.clone(),
+        });
     }
 }
 
 fn add_children<'a, M: 'static>(
-    dom_element: &web_sys::Element,
+    wid: &str,
+    dom_element: NodeId,
     children: impl Iterator<Item = &'a mut Node<M>>,
+    patches: &mut Vec<Patch>,
 ) {
     for child in children {
-        node(child);
+        node(wid, child, patches);
+
+        // unwrapping is safe because 'draw::node()' must have provided
+        patches.push(Patch::Insert {
+            parent: dom_element,
+            child: child.dom_node().unwrap(),
+            before: None,
+        });
+    }
+}
 
-        // unwrapping is safe because 'draw::element()' must have provided
-        match dom_element.append_child(&child.dom_node().unwrap()) {
-            Ok(_appended_child) => (),
-            Err(_) => todo!(),
-        }
+fn add_listeners<M>(
+    wid: &str,
+    dom_element: NodeId,
+    element: &Element<M>,
+    patches: &mut Vec<Patch>,
+) {
+    for (event, listener) in element.listeners.iter() {
+        patches.push(Patch::SetListener {
+            id: dom_element,
+            event,
+            options: listener.options,
+            listener: ListenerId::new(wid, listener),
+        });
     }
 }
 
@@ -1,9 +1,20 @@
-use std::fmt::Debug;
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    rc::Rc,
+};
 
-use crate::nodes::{element::Element, node::Node, update_widget};
-use wasm_bindgen::{prelude::Closure, JsCast};
+use wasm_bindgen::JsCast;
 
-pub fn listen<M>(wname: &str, node: &mut Node<M>)
+use crate::{
+    backend::{self, CallbackId, Event, Handler, ListenerOptions, NodeId},
+    console_log,
+    nodes::{element::Element, node::Node},
+    runtime::Runtime,
+};
+
+pub fn listen<M>(wid: &str, node: &mut Node<M>)
 where
     M: 'static,
 {
@@ -14,11 +25,11 @@ where
             dom_element: Some(dom_element),
             ..
         }) => {
-            for (name, listener) in listeners.iter_mut() {
-                add_event_listener(wname, name, listener, dom_element);
+            for (name, listener) in listeners.iter() {
+                add_event_listener(wid, name, listener, *dom_element);
             }
             for child in children {
-                listen(wname, child)
+                listen(wid, child)
             }
         }
         Node::Element(Element {
@@ -29,66 +40,383 @@ where
 }
 
 pub fn add_event_listener<M>(
-    wname: &str,
+    wid: &str,
     name: &'static str,
-    listener: &mut Listener<M>,
-    dom_element: &web_sys::Element,
+    listener: &Listener<M>,
+    dom_element: NodeId,
 ) where
     M: 'static,
 {
-    let wname = wname.to_string();
-    let cb = match listener.kind {
-        Factory(fac) => Closure::wrap(Box::new(move || {
-            let msg = fac();
-            update_widget(&wname, msg);
-        }) as Box<dyn FnMut()>)
-        .into_js_value()
-        .unchecked_into(),
-
-        Evented(cb) => Closure::wrap(Box::new(move |e: web_sys::Event| {
-            let msg = cb(e);
-            update_widget(&wname, msg);
-        }) as Box<dyn FnMut(web_sys::Event)>)
-        .into_js_value()
-        .unchecked_into(),
-
-        Valued(cb) => Closure::wrap(Box::new(move |e: web_sys::Event| {
-            let msg = cb(e
-                .target()
-                .unwrap()
-                .dyn_ref::<web_sys::Element>()
-                .unwrap()
-                .node_value()
-                .expect("This node doesn't have a value!"));
-            update_widget(&wname, msg);
-        }) as Box<dyn FnMut(web_sys::Event)>)
-        .into_js_value()
-        .unchecked_into(),
-
-    };
-
-    match dom_element.add_event_listener_with_callback(name, &cb) {
-        Ok(()) => (),
-        Err(_) => todo!(),
-    };
-
-    listener.callback = Some(cb)
-}
-
-#[derive(Clone, Debug)]
+    backend::current().add_listener(dom_element, name, listener.options, handler(wid, listener))
+}
+
+/// The handler that sends the message of `listener` to the widget `wid`,
+/// if it makes one.
+pub(crate) fn handler<M: 'static>(wid: &str, listener: &Listener<M>) -> Handler {
+    let runtime = Runtime::current();
+    let wid = wid.to_string();
+    let kind = listener.kind.clone();
+    let send = move |msg: M| runtime.update_widget(&wid, msg);
+    match listener.timing {
+        None => Rc::new(move |e: Event| {
+            if let Some(msg) = kind.message(e) {
+                send(msg)
+            }
+        }),
+        Some(timing) => {
+            let paced = Rc::new(Paced {
+                timing,
+                pending: RefCell::new(None),
+                timer: Cell::new(None),
+                send: Box::new(send),
+            });
+            Rc::new(move |e: Event| {
+                // the event is only around now, so its message is made
+                // right away, even when it is sent later
+                if let Some(msg) = kind.message(e) {
+                    paced.message(msg)
+                }
+            })
+        }
+    }
+}
+
+/// How often a listener sends its messages at most, with timers of the
+/// backend, so that tests can run them on the virtual clock of `Memory`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Timing {
+    /// Sends the last message once there haven't been any for this many
+    /// milliseconds, like for a search box.
+    Debounce(u32),
+    /// Sends a message right away, and then at most one per this many
+    /// milliseconds, the last one of those in between.
+    Throttle(u32),
+}
+
+/// What a debounced or throttled listener keeps between events.
+struct Paced<M> {
+    timing: Timing,
+    /// The message waiting for the timer.
+    pending: RefCell<Option<M>>,
+    timer: Cell<Option<CallbackId>>,
+    send: Box<dyn Fn(M)>,
+}
+
+impl<M: 'static> Paced<M> {
+    fn message(self: &Rc<Self>, msg: M) {
+        match self.timing {
+            Timing::Debounce(ms) => {
+                *self.pending.borrow_mut() = Some(msg);
+                if let Some(timer) = self.timer.take() {
+                    backend::current().cancel(timer);
+                }
+                self.wait(ms);
+            }
+            Timing::Throttle(_) if self.timer.get().is_some() => {
+                *self.pending.borrow_mut() = Some(msg);
+            }
+            Timing::Throttle(ms) => {
+                (self.send)(msg);
+                self.wait(ms);
+            }
+        }
+    }
+
+    fn wait(self: &Rc<Self>, ms: u32) {
+        // the listener may be gone by the time the timer runs out
+        let paced = Rc::downgrade(self);
+        let timer = backend::current().set_timeout(
+            ms,
+            Rc::new(move || {
+                if let Some(paced) = paced.upgrade() {
+                    paced.elapsed()
+                }
+            }),
+        );
+        self.timer.set(Some(timer));
+    }
+
+    fn elapsed(self: &Rc<Self>) {
+        self.timer.set(None);
+        let pending = self.pending.take();
+        if let Some(msg) = pending {
+            (self.send)(msg);
+            if let Timing::Throttle(ms) = self.timing {
+                self.wait(ms);
+            }
+        }
+    }
+}
+
+impl<M> Drop for Paced<M> {
+    fn drop(&mut self) {
+        // the backend may be the one dropping the listener, like when
+        // the thread ends
+        if let (Some(timer), Some(backend)) = (self.timer.take(), backend::try_current()) {
+            backend.cancel(timer);
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Listener<M: 'static> {
     pub kind: ListenerKind<M>,
-    pub callback: Option<js_sys::Function>,
+    pub options: ListenerOptions,
+    pub timing: Option<Timing>,
+}
+
+impl<M> Listener<M> {
+    pub fn new(kind: ListenerKind<M>) -> Self {
+        Self {
+            kind,
+            options: ListenerOptions::default(),
+            timing: None,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+// listeners with other options are bound again, as the dom can't change
+// the options of a listener. The ones that stay keep their timers.
+impl<M> PartialEq for Listener<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.options == other.options && self.timing == other.timing
+    }
+}
+
+impl<M> Clone for Listener<M> {
+    fn clone(&self) -> Self {
+        Self {
+            kind: self.kind.clone(),
+            options: self.options,
+            timing: self.timing,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum ListenerKind<M: 'static> {
     Factory(fn() -> M),
     Evented(fn(web_sys::Event) -> M),
+    /// Like `Evented`, but only sends a message when it makes one, like
+    /// for the one key of all keydown events that matters. As it needs a
+    /// dom event, only the dom backends fire it.
+    Maybe(fn(web_sys::Event) -> Option<M>),
     Valued(fn(String) -> M),
+    Checked(fn(bool) -> M),
+    Files(fn(Vec<web_sys::File>) -> M),
+    /// A listener getting the event as one of the types `web_sys` has for
+    /// them, like a `MouseEvent`.
+    Typed(Rc<dyn AnyListener<M>>),
+    /// A listener of a view with another message type, as made by `map`.
+    Mapped(Rc<dyn AnyListener<M>>),
+    /// A closure, which is bound again only when its dependencies change.
+    Closure(Rc<dyn AnyListener<M>>),
 }
 pub use ListenerKind::*;
 
+impl<M: 'static> ListenerKind<M> {
+    /// The message `e` makes, if any.
+    pub(crate) fn message(&self, e: Event) -> Option<M> {
+        match self {
+            Factory(fac) => Some(fac()),
+            Evented(cb) => Some(cb(e.dom_event())),
+            Maybe(cb) => cb(e.dom_event()),
+            Valued(cb) => Some(cb(e.value())),
+            Checked(cb) => Some(cb(e.checked())),
+            Files(cb) => Some(cb(e.files())),
+            Typed(typed) => typed.message(e),
+            Mapped(mapped) => mapped.message(e),
+            Closure(closure) => closure.message(e),
+        }
+    }
+
+    /// A listener calling `f`, which can capture values, like the id of
+    /// the item a button deletes. Closures can't be compared, so it is
+    /// bound again only when `deps` differs from the previous render,
+    /// which should hold everything `f` captures.
+    pub fn closure<D>(deps: D, f: impl Fn(Event) -> M + 'static) -> Self
+    where
+        D: PartialEq + 'static,
+    {
+        Closure(Rc::new(WithDeps { deps, f }))
+    }
+
+    /// A listener getting the event as an `E`, like a `web_sys::KeyboardEvent`.
+    pub(crate) fn typed<E: JsCast + 'static>(f: fn(E) -> M) -> Self {
+        Typed(Rc::new(Cast(Call::Always(f))))
+    }
+
+    /// Like `typed`, but only sends a message when `f` makes one.
+    pub(crate) fn typed_maybe<E: JsCast + 'static>(f: fn(E) -> Option<M>) -> Self {
+        Typed(Rc::new(Cast(Call::Maybe(f))))
+    }
+
+    /// Turns the messages of this listener into others with `f`.
+    pub fn map<N: 'static>(self, f: fn(M) -> N) -> ListenerKind<N> {
+        Mapped(Rc::new(Map { kind: self, f }))
+    }
+}
+
+/// A listener that can't be matched on, with the types it is made of
+/// erased.
+pub trait AnyListener<M>: Debug {
+    fn message(&self, e: Event) -> Option<M>;
+    fn as_any(&self) -> &dyn Any;
+    /// Whether `other` is a listener of the same type that makes the
+    /// same messages.
+    fn eq_any(&self, other: &dyn Any) -> bool;
+}
+
+struct Map<M: 'static, N> {
+    kind: ListenerKind<M>,
+    f: fn(M) -> N,
+}
+
+impl<M, N> Debug for Map<M, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Map").finish_non_exhaustive()
+    }
+}
+
+impl<M: 'static, N: 'static> AnyListener<N> for Map<M, N> {
+    fn message(&self, e: Event) -> Option<N> {
+        self.kind.message(e).map(self.f)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn eq_any(&self, other: &dyn Any) -> bool {
+        match other.downcast_ref::<Self>() {
+            Some(other) => self.kind == other.kind && std::ptr::fn_addr_eq(self.f, other.f),
+            None => false,
+        }
+    }
+}
+
+struct Cast<E, M>(Call<E, M>);
+
+/// The function of a typed listener.
+enum Call<E, M> {
+    Always(fn(E) -> M),
+    Maybe(fn(E) -> Option<M>),
+}
+
+impl<E, M> Debug for Cast<E, M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cast")
+            .field("event", &std::any::type_name::<E>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E: JsCast + 'static, M: 'static> AnyListener<M> for Cast<E, M> {
+    fn message(&self, e: Event) -> Option<M> {
+        let e = match e.dom_event().dyn_into::<E>() {
+            Ok(e) => e,
+            // like an event a script made with `new Event("click")`
+            Err(e) => {
+                console_log!(
+                    "[events] dropped a {} event that isn't a {}",
+                    e.type_(),
+                    std::any::type_name::<E>()
+                );
+                return None;
+            }
+        };
+        match self.0 {
+            Call::Always(f) => Some(f(e)),
+            Call::Maybe(f) => f(e),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn eq_any(&self, other: &dyn Any) -> bool {
+        match other.downcast_ref::<Self>() {
+            Some(Cast(other)) => match (&self.0, other) {
+                (Call::Always(f1), Call::Always(f2)) => std::ptr::fn_addr_eq(*f1, *f2),
+                (Call::Maybe(f1), Call::Maybe(f2)) => std::ptr::fn_addr_eq(*f1, *f2),
+                (_, _) => false,
+            },
+            None => false,
+        }
+    }
+}
+
+struct WithDeps<D, F> {
+    deps: D,
+    f: F,
+}
+
+impl<D, F> Debug for WithDeps<D, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WithDeps").finish_non_exhaustive()
+    }
+}
+
+impl<M, D, F> AnyListener<M> for WithDeps<D, F>
+where
+    D: PartialEq + 'static,
+    F: Fn(Event) -> M + 'static,
+{
+    fn message(&self, e: Event) -> Option<M> {
+        Some((self.f)(e))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    // every closure has a type of its own, so one made at another place
+    // in the view never equals this one
+    fn eq_any(&self, other: &dyn Any) -> bool {
+        match other.downcast_ref::<Self>() {
+            Some(other) => self.deps == other.deps,
+            None => false,
+        }
+    }
+}
+
+// derived Clone would require M: Clone, which messages don't need to be
+impl<M> Clone for ListenerKind<M> {
+    fn clone(&self) -> Self {
+        match self {
+            Factory(fac) => Factory(*fac),
+            Evented(cb) => Evented(*cb),
+            Maybe(cb) => Maybe(*cb),
+            Valued(cb) => Valued(*cb),
+            Checked(cb) => Checked(*cb),
+            Files(cb) => Files(*cb),
+            Typed(typed) => Typed(typed.clone()),
+            Mapped(mapped) => Mapped(mapped.clone()),
+            Closure(closure) => Closure(closure.clone()),
+        }
+    }
+}
+
+// listeners are the same when they call the same function, closures
+// when their dependencies are the same
+impl<M> PartialEq for ListenerKind<M> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Factory(fac1), Factory(fac2)) => std::ptr::fn_addr_eq(*fac1, *fac2),
+            (Evented(cb1), Evented(cb2)) => std::ptr::fn_addr_eq(*cb1, *cb2),
+            (Maybe(cb1), Maybe(cb2)) => std::ptr::fn_addr_eq(*cb1, *cb2),
+            (Valued(cb1), Valued(cb2)) => std::ptr::fn_addr_eq(*cb1, *cb2),
+            (Checked(cb1), Checked(cb2)) => std::ptr::fn_addr_eq(*cb1, *cb2),
+            (Files(cb1), Files(cb2)) => std::ptr::fn_addr_eq(*cb1, *cb2),
+            (Typed(typed1), Typed(typed2)) => typed1.eq_any(typed2.as_any()),
+            (Mapped(mapped1), Mapped(mapped2)) => mapped1.eq_any(mapped2.as_any()),
+            (Closure(closure1), Closure(closure2)) => closure1.eq_any(closure2.as_any()),
+            (_, _) => false,
+        }
+    }
+}
+
 impl<M> From<fn() -> M> for ListenerKind<M> {
     fn from(x: fn() -> M) -> Self {
         Factory(x)
@@ -101,10 +429,155 @@ impl<M> From<fn(web_sys::Event) -> M> for ListenerKind<M> {
     }
 }
 
+impl<M> From<fn(web_sys::Event) -> Option<M>> for ListenerKind<M> {
+    fn from(x: fn(web_sys::Event) -> Option<M>) -> Self {
+        Maybe(x)
+    }
+}
+
 impl<M> From<fn(String) -> M> for ListenerKind<M> {
     fn from(x: fn(String) -> M) -> Self {
         Valued(x)
     }
 }
 
+impl<M> From<fn(bool) -> M> for ListenerKind<M> {
+    fn from(x: fn(bool) -> M) -> Self {
+        Checked(x)
+    }
+}
+
+impl<M> From<fn(Vec<web_sys::File>) -> M> for ListenerKind<M> {
+    fn from(x: fn(Vec<web_sys::File>) -> M) -> Self {
+        Files(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::api::*;
 
+    /// Shows the values its input sent, paced by `timing`.
+    fn paced(timing: Timing) -> Widget {
+        Widget::new(
+            "paced",
+            timing,
+            Vec::new,
+            |got: &mut Vec<String>, _: &Timing, value: String| {
+                got.push(value);
+                Action::Diff
+            },
+            |got: &Vec<String>, timing: &Timing| -> Html<String> {
+                let input = input().on_value("input", |value| value);
+                let input = match *timing {
+                    Timing::Debounce(ms) => input.debounce(ms),
+                    Timing::Throttle(ms) => input.throttle(ms),
+                };
+                div().child(got.join(",")).child(input)
+            },
+        )
+    }
+
+    /// Runs `paced(timing)`, returning a function that types a value
+    /// and one that shows what arrived.
+    fn start(timing: Timing) -> (Rc<Memory>, impl Fn(&str), impl Fn() -> String) {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| paced(timing));
+        let el = memory.children(memory.root())[0];
+        let input = memory.children(el)[1];
+        let typed = memory.clone();
+        let shown = memory.clone();
+        (
+            memory,
+            move |value: &str| {
+                let event = MemoryEvent {
+                    value: Some(value.to_string()),
+                    ..Default::default()
+                };
+                typed.dispatch(input, "input", event);
+            },
+            move || {
+                runtime.flush();
+                shown.html(shown.children(el)[0])
+            },
+        )
+    }
+
+    #[test]
+    fn debounce_sends_the_last_value_once_typing_stops() {
+        let (memory, type_, shown) = start(Timing::Debounce(100));
+        type_("a");
+        memory.advance(50);
+        type_("ab");
+        memory.advance(99);
+        assert_eq!(shown(), "");
+        memory.advance(1);
+        assert_eq!(shown(), "ab");
+
+        type_("abc");
+        memory.advance(1000);
+        assert_eq!(shown(), "ab,abc");
+    }
+
+    #[test]
+    fn throttle_sends_right_away_and_then_the_last_value_per_interval() {
+        let (memory, type_, shown) = start(Timing::Throttle(100));
+        type_("a");
+        assert_eq!(shown(), "a");
+        type_("ab");
+        type_("abc");
+        memory.advance(99);
+        assert_eq!(shown(), "a");
+        memory.advance(1);
+        assert_eq!(shown(), "a,abc");
+
+        // the interval after a pending value passes without one
+        memory.advance(100);
+        type_("abcd");
+        assert_eq!(shown(), "a,abc,abcd");
+    }
+
+    /// Shows which of its listeners the clicks on its button went through.
+    fn options() -> Widget {
+        Widget::new(
+            "options",
+            (),
+            Vec::new,
+            |got: &mut Vec<u8>, _: &(), n: u8| {
+                got.push(n);
+                Action::Diff
+            },
+            |got: &Vec<u8>, _: &()| -> Html<u8> {
+                let got: Vec<_> = got.iter().map(u8::to_string).collect();
+                let button = button().on("click", || 2).once().stop_propagation();
+                let inner = div()
+                    .on("click", || 1)
+                    .capture()
+                    .child(button.prevent_default());
+                form().on("click", || 3).child(got.join(",")).child(inner)
+            },
+        )
+    }
+
+    #[test]
+    fn listens_with_the_options_of_a_listener() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(options);
+        let el = memory.children(memory.root())[0];
+        let inner = memory.children(el)[1];
+        let button = memory.children(inner)[0];
+
+        assert!(memory.dispatch(button, "click", MemoryEvent::default()));
+        runtime.flush();
+        assert_eq!(memory.html(memory.children(el)[0]), "1,2");
+
+        // the button listened once, and the click bubbles up from it now
+        assert!(!memory.dispatch(button, "click", MemoryEvent::default()));
+        runtime.flush();
+        assert_eq!(memory.html(memory.children(el)[0]), "1,2,1,3");
+    }
+}
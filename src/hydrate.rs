@@ -0,0 +1,386 @@
+//! hydrate.rs adopts markup that was rendered on the server. The widget
+//! tree is compared to the existing dom first, and only when they match
+//! the virtual nodes are pointed at the existing dom nodes and listened
+//! to. Otherwise the server markup is replaced by a fresh render.
+
+use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
+
+use crate::{
+    backend::{self, next_id, Backend, NodeId},
+    console_log, events,
+    nodes::{node::Node, widget::Widget},
+    patch,
+    runtime::{AnyInstance, Runtime},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// Found a different element, or a text (`None`) where an element was expected.
+    Tag {
+        expected: String,
+        found: Option<String>,
+    },
+    Attributes {
+        tag: String,
+        expected: Vec<(String, String)>,
+        found: Vec<(String, String)>,
+    },
+    /// Found a different text, or an element (`None`) where a text was expected.
+    Text {
+        expected: String,
+        found: Option<String>,
+    },
+    Children {
+        tag: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Tag { expected, found } => {
+                write!(f, "expected <{}>, found {:?}", expected, found)
+            }
+            Mismatch::Attributes {
+                tag,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected attributes {:?} on <{}>, found {:?}",
+                expected, tag, found
+            ),
+            Mismatch::Text { expected, found } => {
+                write!(f, "expected text {:?}, found {:?}", expected, found)
+            }
+            Mismatch::Children {
+                tag,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected {} children in <{}>, found {}",
+                expected, tag, found
+            ),
+        }
+    }
+}
+
+pub(crate) struct Hydration {
+    backend: Rc<dyn Backend>,
+    /// The dom nodes matching the virtual nodes, in tree order.
+    found: VecDeque<NodeId>,
+    /// The dom nodes the backend handed out ids for while checking.
+    adopted: Vec<NodeId>,
+    mismatches: Vec<Mismatch>,
+}
+
+/// Makes `widget` take over the single child of `mount`, returning the
+/// mismatches that made it render from scratch instead.
+pub fn hydrate(widget: &mut Widget, mount: NodeId) -> Vec<Mismatch> {
+    // nothing is drawn yet, so that a match doesn't leave fresh nodes behind
+    instantiate_widget(widget);
+    let mut hydration = Hydration {
+        backend: backend::current(),
+        found: VecDeque::new(),
+        adopted: Vec::new(),
+        mismatches: Vec::new(),
+    };
+
+    let existing = hydration.backend.children(mount);
+    match existing.as_slice() {
+        [dom_node] => check_widget(widget, *dom_node, &mut hydration),
+        _ => hydration.mismatches.push(Mismatch::Children {
+            tag: hydration.backend.tag(mount).unwrap_or_default(),
+            expected: 1,
+            found: existing.len(),
+        }),
+    }
+
+    if hydration.mismatches.is_empty() {
+        adopt_widget(widget, &mut hydration);
+    } else {
+        for mismatch in hydration.mismatches.iter() {
+            console_log!("[hydrate] {}, rendering again", mismatch);
+        }
+        for dom_node in existing {
+            hydration.backend.remove_child(dom_node);
+            release(&*hydration.backend, dom_node);
+        }
+        // backends that adopt nodes as they are read hand out new ids
+        // every time, so the ones of the check are released as well
+        for dom_node in hydration.adopted.drain(..) {
+            hydration.backend.release(dom_node);
+        }
+        let mut patches = Vec::new();
+        widget.mount(&mut patches);
+        patch::apply(patches);
+        hydration
+            .backend
+            .insert_child(mount, widget.dom_node().unwrap(), None);
+    }
+
+    hydration.mismatches
+}
+
+/// Releases the server markup in `dom_node`, down to the texts in
+/// elements that were never checked.
+fn release(backend: &dyn Backend, dom_node: NodeId) {
+    for child in backend.children(dom_node) {
+        release(backend, child);
+    }
+    backend.release(dom_node);
+}
+
+fn instantiate_widget(widget: &mut Widget) {
+    widget.instantiate();
+    widget_instance(widget)
+        .borrow_mut()
+        .node_mut()
+        .instantiate()
+}
+
+pub(crate) fn instantiate_node<M>(node: &mut Node<M>) {
+    match node {
+        Node::Element(el) => {
+            for child in el.children.iter_mut() {
+                instantiate_node(child);
+            }
+        }
+        Node::Text(_) => {}
+        Node::Widget(widget) => instantiate_widget(widget),
+    }
+}
+
+fn check_widget(widget: &Widget, dom_node: NodeId, hydration: &mut Hydration) {
+    let instance = widget_instance(widget);
+    let instance = instance.borrow();
+    instance.node().check(dom_node, hydration)
+}
+
+pub(crate) fn check_node<M>(node: &Node<M>, dom_node: NodeId, hydration: &mut Hydration) {
+    let backend = hydration.backend.clone();
+    match node {
+        Node::Element(el) => {
+            hydration.found.push_back(dom_node);
+
+            let tag = backend.tag(dom_node);
+            if tag.as_deref() != Some(el.tag) {
+                return hydration.mismatches.push(Mismatch::Tag {
+                    expected: el.tag.to_string(),
+                    found: tag,
+                });
+            }
+
+            let mut expected: Vec<_> = el
+                .attrs
+                .iter()
+                .map(|(name, val)| (name.to_string(), val.clone()))
+                .collect();
+            let mut found = backend.attributes(dom_node);
+            expected.sort();
+            found.sort();
+            if expected != found {
+                hydration.mismatches.push(Mismatch::Attributes {
+                    tag: el.tag.to_string(),
+                    expected,
+                    found,
+                });
+            }
+
+            let children = backend.children(dom_node);
+            hydration.adopted.extend(children.iter().copied());
+            let expected: Vec<_> = el.children.iter().filter(|c| rendered(c)).collect();
+            if children.len() != expected.len() {
+                return hydration.mismatches.push(Mismatch::Children {
+                    tag: el.tag.to_string(),
+                    expected: expected.len(),
+                    found: children.len(),
+                });
+            }
+            for (child, dom_child) in expected.into_iter().zip(children) {
+                check_node(child, dom_child, hydration);
+            }
+        }
+        Node::Text(txt) => {
+            hydration.found.push_back(dom_node);
+
+            let found = backend.text(dom_node);
+            if found.as_deref() != Some(&*txt.val) {
+                hydration.mismatches.push(Mismatch::Text {
+                    expected: txt.val.clone(),
+                    found,
+                });
+            }
+        }
+        Node::Widget(widget) => check_widget(widget, dom_node, hydration),
+    }
+}
+
+fn adopt_widget(widget: &mut Widget, hydration: &mut Hydration) {
+    let instance = widget_instance(widget);
+    let mut instance = instance.borrow_mut();
+    // unwrapping is safe because the widget has been instantiated
+    let id = widget.id.as_ref().unwrap();
+    instance.node_mut().adopt(id, hydration);
+    instance.mounted(id);
+}
+
+fn widget_instance(widget: &Widget) -> Rc<RefCell<dyn AnyInstance>> {
+    // unwrapping is safe because the widget has been instantiated
+    let id = widget.id.as_ref().unwrap();
+    Runtime::current().instance(id).unwrap()
+}
+
+pub(crate) fn adopt_node<M: 'static>(wid: &str, node: &mut Node<M>, hydration: &mut Hydration) {
+    adopt_ids(node, hydration);
+    events::listen(wid, node);
+}
+
+/// Whether the server rendered a dom node for `node`, which it doesn't
+/// for an empty text.
+fn rendered<M>(node: &Node<M>) -> bool {
+    !matches!(node, Node::Text(txt) if txt.val.is_empty())
+}
+
+fn adopt_ids<M>(node: &mut Node<M>, hydration: &mut Hydration) {
+    match node {
+        Node::Element(el) => {
+            el.dom_element = hydration.found.pop_front();
+            for child in el.children.iter_mut().filter(|c| rendered(c)) {
+                adopt_ids(child, hydration);
+            }
+            // the empty texts get fresh dom nodes, in between the others
+            let mut next = None;
+            for child in el.children.iter_mut().rev() {
+                if let Node::Text(txt) = child {
+                    if txt.dom_text_node.is_none() {
+                        let id = next_id();
+                        hydration.backend.create_text(id, "");
+                        // unwrapping is safe because the element has been adopted
+                        let parent = el.dom_element.unwrap();
+                        hydration.backend.insert_child(parent, id, next);
+                        txt.dom_text_node = Some(id);
+                    }
+                }
+                next = child.dom_node();
+            }
+        }
+        Node::Text(txt) => txt.dom_text_node = hydration.found.pop_front(),
+        Node::Widget(widget) => adopt_widget(widget, hydration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{api::*, backend::next_id};
+
+    fn app() -> Widget {
+        Widget::new("app", (), || 0, update, view)
+    }
+
+    fn update(count: &mut u32, _: &(), _: ()) -> Action<()> {
+        *count += 1;
+        Action::Diff
+    }
+
+    fn view(count: &u32, _: &()) -> Html<()> {
+        button().on("click", || ()).child(count.to_string())
+    }
+
+    /// Puts `<div id="app"><tag>0</tag></div>` in the root, like a server would.
+    fn server_markup(memory: &Memory, tag: &str) -> (NodeId, NodeId) {
+        let (mount, element, text) = (next_id(), next_id(), next_id());
+        memory.create_element(mount, "div");
+        memory.set_attribute(mount, "id", "app");
+        memory.create_element(element, tag);
+        memory.create_text(text, "0");
+        memory.insert_child(element, text, None);
+        memory.insert_child(mount, element, None);
+        memory.insert_child(memory.root(), mount, None);
+        (mount, element)
+    }
+
+    #[test]
+    fn adopts_matching_markup_without_drawing() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let (mount, button) = server_markup(&memory, "button");
+        let nodes = memory.node_count();
+
+        let (runtime, mismatches) = hydrate(app, "app");
+        assert_eq!(mismatches, vec![]);
+        assert_eq!(memory.node_count(), nodes);
+
+        memory.dispatch(button, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(
+            memory.html(mount),
+            "<div id=\"app\"><button>1</button></div>"
+        );
+    }
+
+    #[test]
+    fn renders_again_when_markup_differs() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let (mount, _) = server_markup(&memory, "p");
+        let nodes = memory.node_count();
+
+        let (_, mismatches) = hydrate(app, "app");
+        assert_eq!(
+            mismatches,
+            vec![Mismatch::Tag {
+                expected: "button".to_string(),
+                found: Some("p".to_string()),
+            }]
+        );
+        assert_eq!(memory.node_count(), nodes);
+        assert_eq!(
+            memory.html(mount),
+            "<div id=\"app\"><button>0</button></div>"
+        );
+    }
+
+    /// Shows nothing until clicked, and then the number of clicks.
+    fn empty() -> Widget {
+        Widget::new(
+            "empty",
+            (),
+            || 0,
+            update,
+            |count: &u32, _: &()| -> Html<()> {
+                let el = p().on("click", || ()).child("");
+                match count {
+                    0 => el,
+                    _ => el.child(count.to_string()),
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn adopts_markup_without_nodes_for_empty_texts() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let (mount, el) = (next_id(), next_id());
+        memory.create_element(mount, "div");
+        memory.set_attribute(mount, "id", "app");
+        memory.create_element(el, "p");
+        memory.insert_child(mount, el, None);
+        memory.insert_child(memory.root(), mount, None);
+
+        let (runtime, mismatches) = hydrate(empty, "app");
+        assert_eq!(mismatches, vec![]);
+        assert_eq!(memory.children(el).len(), 1);
+
+        memory.dispatch(el, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(mount), "<div id=\"app\"><p>1</p></div>");
+        assert_eq!(memory.children(el).len(), 2);
+    }
+}
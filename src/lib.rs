@@ -1,9 +1,16 @@
 mod action;
+mod backend;
+mod callback;
 mod diff;
 mod draw;
 mod events;
+mod hydrate;
 mod nodes;
 mod patch;
+mod runtime;
+mod sub;
+#[cfg(feature = "ssr")]
+mod ssr;
 mod utils;
 mod api;
 
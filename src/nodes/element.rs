@@ -1,14 +1,36 @@
 use super::node::Node;
-use crate::events::{Listener, ListenerKind};
+use crate::{
+    backend::NodeId,
+    events::{Listener, ListenerKind, Timing},
+};
 use std::collections::HashMap;
 use std::fmt::Debug;
+use wasm_bindgen::JsCast;
+
+/// Listeners getting the event as the type `web_sys` has for it, so that
+/// no listener can ask for the wrong one.
+macro_rules! typed_listeners {
+    ( $( $method:ident($event:literal, $type:ident) ),* ) => {
+        $(
+            #[doc = concat!("Listens to `", $event, "` events as `", stringify!($type), "`s.")]
+            #[doc = ""]
+            #[doc = "Only the dom backends fire it, as it needs a dom event."]
+            pub fn $method(self, action: fn(web_sys::$type) -> M) -> Self {
+                self.on_typed($event, action)
+            }
+        )*
+    };
+}
 
 pub struct Element<M: 'static> {
     pub tag: &'static str,
     pub attrs: HashMap<&'static str, String>,
     pub children: Vec<Node<M>>,
-    pub dom_element: Option<web_sys::Element>,
+    pub key: Option<String>,
+    pub dom_element: Option<NodeId>,
     pub listeners: HashMap<&'static str, Listener<M>>,
+    /// The event listened to last, which the listener options apply to.
+    last_listener: Option<&'static str>,
 }
 
 impl<M: Debug> Debug for Element<M> {
@@ -23,6 +45,10 @@ impl<M: Debug> Debug for Element<M> {
             x = x.field(name, &listener.kind)
         }
 
+        if let Some(key) = &self.key {
+            x = x.field("key", key);
+        }
+
         x.field("children", &self.children)
             .field("dom", &self.dom_element.is_some())
             .finish()
@@ -35,8 +61,10 @@ impl<M> Element<M> {
             tag,
             attrs: HashMap::new(),
             children: Vec::new(),
+            key: None,
             dom_element: None,
             listeners: HashMap::new(),
+            last_listener: None,
         }
     }
 
@@ -50,33 +78,244 @@ impl<M> Element<M> {
         self
     }
 
+    /// Identifies this element among its siblings, so that reordering
+    /// a list moves the existing dom nodes instead of rewriting them.
+    pub fn key(mut self, key: impl ToString) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
     pub fn on(self, event: &'static str, action: fn() -> M) -> Self {
         self.on_listener_kind(event, action.into())
     }
 
+    /// Listens with the value of an input, textarea or select, like
+    /// `on_value("input", Msg::NameChanged)`.
     pub fn on_value(self, event: &'static str, action: fn(String) -> M) -> Self {
         self.on_listener_kind(event, action.into())
     }
 
+    /// Listens with whether a checkbox or radio button is checked. Panics
+    /// when the event comes from any other element.
+    pub fn on_checked(self, event: &'static str, action: fn(bool) -> M) -> Self {
+        self.on_listener_kind(event, action.into())
+    }
+
+    /// Listens with the files picked with an `<input type="file">`.
+    pub fn on_files(self, event: &'static str, action: fn(Vec<web_sys::File>) -> M) -> Self {
+        self.on_listener_kind(event, action.into())
+    }
+
     pub fn on_event(self, event: &'static str, action: fn(web_sys::Event) -> M) -> Self {
         self.on_listener_kind(event, action.into())
     }
 
-    pub fn on_listener_kind(mut self, event: &'static str, action: ListenerKind<M>) -> Self {
-        self.listeners.insert(
+    /// Like `on_event`, but the widget only gets a message when `action`
+    /// returns one, and isn't updated otherwise. Only the dom backends
+    /// fire it, as it needs a dom event.
+    pub fn on_event_maybe(
+        self,
+        event: &'static str,
+        action: fn(web_sys::Event) -> Option<M>,
+    ) -> Self {
+        self.on_listener_kind(event, action.into())
+    }
+
+    /// Listens with a closure, like `on_with("click", id, move || Msg::Delete(id))`.
+    /// The listener is bound again when `deps` changes, so it should hold
+    /// the values the closure captures.
+    pub fn on_with<D>(self, event: &'static str, deps: D, action: impl Fn() -> M + 'static) -> Self
+    where
+        D: PartialEq + 'static,
+    {
+        self.on_listener_kind(event, ListenerKind::closure(deps, move |_| action()))
+    }
+
+    pub fn on_value_with<D>(
+        self,
+        event: &'static str,
+        deps: D,
+        action: impl Fn(String) -> M + 'static,
+    ) -> Self
+    where
+        D: PartialEq + 'static,
+    {
+        self.on_listener_kind(
+            event,
+            ListenerKind::closure(deps, move |e| action(e.value())),
+        )
+    }
+
+    pub fn on_event_with<D>(
+        self,
+        event: &'static str,
+        deps: D,
+        action: impl Fn(web_sys::Event) -> M + 'static,
+    ) -> Self
+    where
+        D: PartialEq + 'static,
+    {
+        self.on_listener_kind(
             event,
-            Listener {
-                kind: action,
-                callback: None,
-            },
-        );
+            ListenerKind::closure(deps, move |e| action(e.dom_event())),
+        )
+    }
+
+    /// Listens with the event as an `E`, which has to be the type the
+    /// browser fires `event` with, as the typed listeners below are.
+    pub(crate) fn on_typed<E: JsCast + 'static>(
+        self,
+        event: &'static str,
+        action: fn(E) -> M,
+    ) -> Self {
+        self.on_listener_kind(event, ListenerKind::typed(action))
+    }
+
+    /// Like `on_typed`, but the widget only gets a message when `action`
+    /// returns one.
+    pub(crate) fn on_typed_maybe<E: JsCast + 'static>(
+        self,
+        event: &'static str,
+        action: fn(E) -> Option<M>,
+    ) -> Self {
+        self.on_listener_kind(event, ListenerKind::typed_maybe(action))
+    }
+
+    /// Listens to the keys pressed, sending only the messages `action`
+    /// makes, like for a keyboard shortcut. Only the dom backends fire it.
+    pub fn on_keydown_maybe(self, action: fn(web_sys::KeyboardEvent) -> Option<M>) -> Self {
+        self.on_typed_maybe("keydown", action)
+    }
+
+    pub fn on_keyup_maybe(self, action: fn(web_sys::KeyboardEvent) -> Option<M>) -> Self {
+        self.on_typed_maybe("keyup", action)
+    }
+
+    typed_listeners!(
+        on_click("click", MouseEvent),
+        on_dblclick("dblclick", MouseEvent),
+        on_mousedown("mousedown", MouseEvent),
+        on_mouseup("mouseup", MouseEvent),
+        on_mousemove("mousemove", MouseEvent),
+        on_mouseenter("mouseenter", MouseEvent),
+        on_mouseleave("mouseleave", MouseEvent),
+        on_contextmenu("contextmenu", MouseEvent),
+        on_keydown("keydown", KeyboardEvent),
+        on_keyup("keyup", KeyboardEvent),
+        // not an `InputEvent`, which selects, checkboxes and radio
+        // buttons don't fire
+        on_input("input", Event),
+        on_submit("submit", SubmitEvent),
+        on_focus("focus", FocusEvent),
+        on_blur("blur", FocusEvent),
+        on_wheel("wheel", WheelEvent),
+        on_pointerdown("pointerdown", PointerEvent),
+        on_pointerup("pointerup", PointerEvent),
+        on_pointermove("pointermove", PointerEvent),
+        on_pointerenter("pointerenter", PointerEvent),
+        on_pointerleave("pointerleave", PointerEvent),
+        on_pointercancel("pointercancel", PointerEvent),
+        on_touchstart("touchstart", TouchEvent),
+        on_touchend("touchend", TouchEvent),
+        on_touchmove("touchmove", TouchEvent),
+        on_touchcancel("touchcancel", TouchEvent)
+    );
+
+    pub fn on_listener_kind(mut self, event: &'static str, action: ListenerKind<M>) -> Self {
+        self.listeners.insert(event, Listener::new(action));
+        self.last_listener = Some(event);
         self
     }
 
-    pub fn node(self) -> Node<M> {
-        return Node::Element(self);
+    /// Makes the listener added last wait until its events have stopped
+    /// for `ms` milliseconds, and then send the last message.
+    pub fn debounce(self, ms: u32) -> Self {
+        self.modify_listener(|listener| listener.timing = Some(Timing::Debounce(ms)))
     }
 
-}
+    /// Makes the listener added last send at most one message every `ms`
+    /// milliseconds.
+    pub fn throttle(self, ms: u32) -> Self {
+        self.modify_listener(|listener| listener.timing = Some(Timing::Throttle(ms)))
+    }
+
+    /// Makes the listener added last listen while the event goes down to
+    /// its target, before the listeners it bubbles up to.
+    pub fn capture(self) -> Self {
+        self.modify_listener(|listener| listener.options.capture = true)
+    }
+
+    /// Promises that the listener added last doesn't prevent the default,
+    /// so that the browser can scroll right away.
+    pub fn passive(self) -> Self {
+        self.modify_listener(|listener| listener.options.passive = true)
+    }
+
+    /// Removes the listener added last once it has been called.
+    pub fn once(self) -> Self {
+        self.modify_listener(|listener| listener.options.once = true)
+    }
+
+    /// Prevents the default of the events the listener added last gets,
+    /// like `.on("submit", Msg::Save).prevent_default()` keeps the page.
+    pub fn prevent_default(self) -> Self {
+        self.modify_listener(|listener| listener.options.prevent_default = true)
+    }
+
+    /// Stops the events the listener added last gets from going further.
+    pub fn stop_propagation(self) -> Self {
+        self.modify_listener(|listener| listener.options.stop_propagation = true)
+    }
+
+    fn modify_listener(mut self, f: impl FnOnce(&mut Listener<M>)) -> Self {
+        let listener = self
+            .last_listener
+            .and_then(|event| self.listeners.get_mut(event));
+        match listener {
+            Some(listener) => f(listener),
+            None => panic!(
+                "Listener modifiers apply to the listener added last, but <{}> has none",
+                self.tag
+            ),
+        }
+        self
+    }
 
+    pub fn node(self) -> Node<M> {
+        Node::Element(self)
+    }
 
+    /// Turns the messages of this element and its children into others
+    /// with `f`, to use a view made for another widget.
+    pub fn map<N>(self, f: fn(M) -> N) -> Element<N> {
+        Element {
+            tag: self.tag,
+            attrs: self.attrs,
+            children: self
+                .children
+                .into_iter()
+                .map(|child| child.map(f))
+                .collect(),
+            key: self.key,
+            dom_element: self.dom_element,
+            listeners: self
+                .listeners
+                .into_iter()
+                .map(|(event, listener)| {
+                    let kind = listener.kind.map(f);
+                    let options = listener.options;
+                    let timing = listener.timing;
+                    (
+                        event,
+                        Listener {
+                            kind,
+                            options,
+                            timing,
+                        },
+                    )
+                })
+                .collect(),
+            last_listener: self.last_listener,
+        }
+    }
+}
@@ -1,4 +1,10 @@
 use super::{element::Element, text::Text, widget::Widget};
+use crate::{
+    backend::{self, NodeId},
+    diff,
+    hydrate::{self, Hydration},
+    patch,
+};
 use std::fmt::Debug;
 
 pub enum Node<M: 'static> {
@@ -18,15 +24,29 @@ impl<M: Debug> Debug for Node<M> {
 }
 
 impl<M> Node<M> {
-    pub fn dom_node(&self) -> Option<&web_sys::Node> {
+    pub fn dom_node(&self) -> Option<NodeId> {
         match self {
-            Self::Element(Element { dom_element, .. }) => {
-                dom_element.as_ref().map(|el| el.as_ref())
-            }
-            Self::Text(Text { dom_text_node, .. }) => {
-                dom_text_node.as_ref().map(|txt| txt.as_ref())
-            }
-            Self::Widget(widget) => Some(&widget.dom_node),
+            Self::Element(Element { dom_element, .. }) => *dom_element,
+            Self::Text(Text { dom_text_node, .. }) => *dom_text_node,
+            Self::Widget(widget) => widget.dom_node(),
+        }
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Self::Element(Element { key, .. }) => key.as_deref(),
+            Self::Widget(Widget { key, .. }) => key.as_deref(),
+            Self::Text(_) => None,
+        }
+    }
+
+    /// Turns the messages of this node into others with `f`, like a
+    /// view made for a `ChildMsg` into one for a `ParentMsg`.
+    pub fn map<N>(self, f: fn(M) -> N) -> Node<N> {
+        match self {
+            Self::Element(el) => Node::Element(el.map(f)),
+            Self::Text(txt) => Node::Text(txt),
+            Self::Widget(widget) => Node::Widget(widget),
         }
     }
 
@@ -37,17 +57,58 @@ impl<M> Node<M> {
         }
     }
 
-    pub fn attach(self, to: &web_sys::Node) -> Self {
+    pub fn attach(self, to: NodeId) -> Self {
         self.attach_borrow(to);
         self
     }
 
-    pub fn attach_borrow(&self, to: &web_sys::Node) {
-        let node: &web_sys::Node = self.dom_node().unwrap();
-        match to.append_child(node) {
-            Ok(_appended_child) => (),
-            Err(_) => todo!(),
-        };
+    pub fn attach_borrow(&self, to: NodeId) {
+        let node = self.dom_node().unwrap();
+        let backend = backend::current();
+        backend.insert_child(to, node, None);
+        backend.flush();
+    }
+}
+
+/// A `Node<M>` with its message type erased, as widget instances hand them out.
+pub(crate) trait AnyNode {
+    #[cfg(feature = "ssr")]
+    fn render(&self, out: &mut String);
+    fn dom_node(&self) -> Option<NodeId>;
+    /// Creates the instances of the widgets in this node, without drawing them.
+    fn instantiate(&mut self);
+    fn check(&self, dom_node: NodeId, hydration: &mut Hydration);
+    fn adopt(&mut self, wid: &str, hydration: &mut Hydration);
+    /// Unmounts the widgets in this node and releases its dom nodes.
+    fn release(&self);
+}
+
+impl<M: 'static> AnyNode for Node<M> {
+    #[cfg(feature = "ssr")]
+    fn render(&self, out: &mut String) {
+        crate::ssr::render_node(self, out)
+    }
+
+    fn dom_node(&self) -> Option<NodeId> {
+        Node::dom_node(self)
+    }
+
+    fn instantiate(&mut self) {
+        hydrate::instantiate_node(self)
+    }
+
+    fn check(&self, dom_node: NodeId, hydration: &mut Hydration) {
+        hydrate::check_node(self, dom_node, hydration)
+    }
+
+    fn adopt(&mut self, wid: &str, hydration: &mut Hydration) {
+        hydrate::adopt_node(wid, self, hydration)
+    }
+
+    fn release(&self) {
+        let mut patches = Vec::new();
+        diff::release(self, &mut patches);
+        patch::apply(patches)
     }
 }
 
@@ -1,11 +1,12 @@
 use std::fmt::Debug;
 use super::node::Node;
+use crate::backend::NodeId;
 
 
 #[derive(Clone)]
 pub struct Text {
     pub val: String,
-    pub dom_text_node: Option<web_sys::Text>,
+    pub dom_text_node: Option<NodeId>,
 }
 
 impl Text {
@@ -16,7 +17,7 @@ impl Text {
         }
     }
     pub fn node<M>(self) -> Node<M> {
-        return Node::Text(self);
+        Node::Text(self)
     }
 }
 
@@ -1,15 +1,113 @@
-use std::{any::Any, collections::hash_map::Entry};
+use std::{any::Any, cell::RefCell, fmt, rc::Rc};
 
-use crate::{action::Action, diff, draw, events};
+use crate::{
+    action::{Action, Effects},
+    backend::{self, NodeId},
+    diff, draw,
+    patch::Patch,
+    runtime::{AnyInstance, Runtime},
+    sub::{self, Sub},
+};
 
-use wasm_rs_async_executor::single_threaded::{spawn};
+use super::node::{AnyNode, Node};
 
-use super::{node::Node, nodes, properties, states, update_widget, updates};
-
-#[derive(Clone, Debug)]
+/// A widget as it appears in a view. It only describes the widget, and
+/// is mounted when the view is drawn. Which instance it belongs to follows
+/// from its place among its siblings, or from its key, just like elements.
 pub struct Widget {
     pub name: String,
-    pub dom_node: web_sys::Node,
+    pub key: Option<String>,
+    /// The instance in the runtime, once mounted.
+    pub(crate) id: Option<String>,
+    spec: Option<Box<dyn AnySpec>>,
+}
+
+impl fmt::Debug for Widget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut x = f.debug_struct("Widget");
+        x.field("name", &self.name);
+        if let Some(key) = &self.key {
+            x.field("key", key);
+        }
+        x.field("id", &self.id).finish()
+    }
+}
+
+/// What it takes to mount a widget, or to update a mounted one.
+struct Spec<P, S, M: 'static, N> {
+    props: P,
+    state: fn() -> S,
+    update: fn(&mut S, &P, M) -> Action<M>,
+    view: fn(&S, &P) -> N,
+    hooks: Hooks<M>,
+    subscriptions: Option<fn(&S, &P) -> Sub<M>>,
+}
+
+type Hook<M> = fn(WidgetRoot) -> Action<M>;
+
+/// The root node of a widget, as its lifecycle hooks get it.
+#[derive(Clone, Debug)]
+pub struct WidgetRoot {
+    pub id: NodeId,
+    /// The node in the dom, on the backends that have one.
+    pub web_node: Option<web_sys::Node>,
+}
+
+impl WidgetRoot {
+    fn new(id: NodeId) -> Self {
+        let web_node = backend::current().web_node(id);
+        WidgetRoot { id, web_node }
+    }
+}
+
+/// The lifecycle hooks of a widget, which get its root node.
+struct Hooks<M> {
+    on_mount: Option<Hook<M>>,
+    on_update: Option<Hook<M>>,
+    on_unmount: Option<Hook<M>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Lifecycle {
+    Mount,
+    Update,
+    Unmount,
+}
+
+trait AnySpec {
+    /// Sets a lifecycle hook, which has to be a `Hook<M>` of the widget's
+    /// message type.
+    fn set_hook(&mut self, name: &str, lifecycle: Lifecycle, hook: &dyn Any);
+    /// Sets the subscriptions, which have to be a function of the widget's
+    /// state and props to its subscriptions.
+    fn set_subscriptions(&mut self, name: &str, subscriptions: &dyn Any);
+    /// Makes the instance with the virtual dom of its first view, which
+    /// is yet to be drawn or adopted.
+    fn instantiate(self: Box<Self>, id: &str) -> Rc<RefCell<dyn AnyInstance>>;
+    /// Renders the view of a fresh instance to html, without mounting it.
+    #[cfg(feature = "ssr")]
+    fn render(&self, id: &str, out: &mut String);
+    /// Hands the new props to `instance`, listing the patches of the
+    /// render they cause, or gives the spec back when the instance is of
+    /// another widget.
+    fn update(
+        self: Box<Self>,
+        id: &str,
+        instance: &mut dyn AnyInstance,
+        patches: &mut Vec<Patch>,
+    ) -> Result<(), Box<dyn AnySpec>>;
+}
+
+/// What the runtime keeps of a widget between renders.
+pub(crate) struct Instance<P, S, M: 'static, N> {
+    props: P,
+    state: S,
+    node: Node<M>,
+    update: fn(&mut S, &P, M) -> Action<M>,
+    view: fn(&S, &P) -> N,
+    hooks: Hooks<M>,
+    subscriptions: Option<fn(&S, &P) -> Sub<M>>,
+    active: Vec<sub::Active<M>>,
 }
 
 impl Widget {
@@ -18,7 +116,7 @@ impl Widget {
         props: P,
         state: fn() -> S,
         update: fn(&mut S, &P, M) -> Action<M>,
-        view: fn(&S, &P) -> N
+        view: fn(&S, &P) -> N,
     ) -> Widget
     where
         P: PartialEq + 'static,
@@ -26,89 +124,336 @@ impl Widget {
         M: 'static,
         N: Into<Node<M>> + 'static,
     {
-        let state = states()
-            .entry(name.to_string())
-            .or_insert_with(|| Box::new(state()))
-            .downcast_ref::<S>()
-            .unwrap();
-
-        match nodes().entry(name.to_string()) {
-            // diff nodes if props are different
-            Entry::Occupied(mut on) => {
-                match properties().entry(name.to_string()) {
-                    Entry::Occupied(mut op) => {
-                        if op.get().downcast_ref::<P>().unwrap() == &props {
-                            // nothing needs to update
-                        } else {
-                            let mut new_node = view(state, &props).into();
-                            let old_node = on.get_mut().downcast_mut::<Node<M>>().unwrap();
-                            op.insert(Box::new(props));
-                            diff::diff_node(name, old_node, &mut new_node);
-                            on.insert(Box::new(new_node));
-                        }
-                    }
-                    Entry::Vacant(vp) => {
-                        let mut new_node = view(state, &props).into();
-                        let old_node = on.get_mut().downcast_mut::<Node<M>>().unwrap();
-                        vp.insert(Box::new(props));
-                        diff::diff_node(name, old_node, &mut new_node);
-                        on.insert(Box::new(new_node));
-                    }
-                };
-            }
+        Widget {
+            name: name.to_string(),
+            key: None,
+            id: None,
+            spec: Some(Box::new(Spec {
+                props,
+                state,
+                update,
+                view,
+                hooks: Hooks {
+                    on_mount: None,
+                    on_update: None,
+                    on_unmount: None,
+                },
+                subscriptions: None,
+            })),
+        }
+    }
 
-            // draw node and connect children widgets
-            Entry::Vacant(vn) => {
-                let mut new_node = view(state, &props).into();
-                properties().insert(name.to_string(), Box::new(props));
-                draw::node(&mut new_node);
-                events::listen(name, &mut new_node);
-                vn.insert(Box::new(new_node));
-            }
-        };
+    /// Runs `hook` once the widget is in the dom, like to hand its root
+    /// node to a javascript library. Hooks run on every backend, but only
+    /// the dom backends give them a `web_node`.
+    pub fn on_mount<M: 'static>(self, hook: Hook<M>) -> Self {
+        self.hook(Lifecycle::Mount, hook)
+    }
 
-        updates().insert(
-            name.to_string(),
-            Box::new(Box::new(move |name: &str, msg: M| {
-                let props = properties().get(name).unwrap().downcast_ref::<P>().unwrap();
-                let state = states().get_mut(name).unwrap().downcast_mut::<S>().unwrap();
-                let action = update(state, props, msg);
-
-                match action {
-                    Action::Diff => {
-                        let mut new_node = view(state, props).into();
-                        let old_node = nodes().get_mut(name).unwrap().downcast_mut().unwrap();
-                        diff::diff_node(name, old_node, &mut new_node);
-                        nodes().insert(name.to_string(), Box::new(new_node));
-                    }
-                    Action::Nothing => {}
-                    Action::Async(f) => {
-                        let name = name.to_string();
-                        spawn(async move {
-                            let msg = f.await;
-                            update_widget(&name, msg);
-                        });
-                    }
-                }
-            }) as Box<dyn Fn(&str, M)>) as Box<dyn Any>,
-        );
-
-        let dom_node = nodes()
-            .get(name)
-            .unwrap()
-            .downcast_ref::<Node<M>>()
-            .unwrap()
-            .dom_node()
-            .unwrap()
-            .clone();
+    /// Runs `hook` whenever a new render of the widget is in the dom.
+    pub fn on_update<M: 'static>(self, hook: Hook<M>) -> Self {
+        self.hook(Lifecycle::Update, hook)
+    }
 
-        Widget {
-            name: name.to_string(),
-            dom_node,
+    /// Runs `hook` once the widget has left the dom. As the widget is
+    /// gone by then, the action it returns is dropped.
+    pub fn on_unmount<M: 'static>(self, hook: Hook<M>) -> Self {
+        self.hook(Lifecycle::Unmount, hook)
+    }
+
+    /// Keeps the widget subscribed to what `subscriptions` returns for
+    /// its current state and props, for as long as it is mounted.
+    pub fn subscriptions<S: 'static, P: 'static, M: 'static>(
+        mut self,
+        subscriptions: fn(&S, &P) -> Sub<M>,
+    ) -> Self {
+        match &mut self.spec {
+            Some(spec) => spec.set_subscriptions(&self.name, &subscriptions),
+            None => panic!("Widget is mounted already"),
+        }
+        self
+    }
+
+    fn hook<M: 'static>(mut self, lifecycle: Lifecycle, hook: Hook<M>) -> Self {
+        match &mut self.spec {
+            Some(spec) => spec.set_hook(&self.name, lifecycle, &hook),
+            None => panic!("Widget is mounted already"),
         }
+        self
+    }
+
+    /// Identifies this widget among its siblings, so that every item of
+    /// a list keeps its own state when the list is reordered.
+    pub fn key(mut self, key: impl ToString) -> Self {
+        self.key = Some(key.to_string());
+        self
     }
 
     pub fn node<M>(self) -> Node<M> {
         Node::Widget(self)
     }
+
+    /// Creates a new instance in the current runtime, unless hydration
+    /// has done so already, and lists the patches that draw it.
+    pub(crate) fn mount(&mut self, patches: &mut Vec<Patch>) {
+        if self.id.is_none() {
+            self.instantiate();
+        }
+        // unwrapping is safe because the widget has just been instantiated
+        let id = self.id.as_ref().unwrap();
+        let instance = Runtime::current().instance(id).unwrap();
+        instance.borrow_mut().draw(id, patches);
+    }
+
+    /// Creates a new instance in the current runtime, without drawing it.
+    pub(crate) fn instantiate(&mut self) {
+        let runtime = Runtime::current();
+        let id = runtime.widget_id(&self.name);
+        let spec = self.spec.take().expect("Widget is mounted already");
+        runtime.insert_instance(&id, spec.instantiate(&id));
+        self.id = Some(id);
+    }
+
+    /// Renders the widget to html: the instance when it is mounted, or
+    /// else the view of a fresh one, which is made in a runtime of its
+    /// own and dropped right after.
+    #[cfg(feature = "ssr")]
+    pub(crate) fn render(&self, out: &mut String) {
+        if let Some(id) = &self.id {
+            let instance = Runtime::current().instance(id).unwrap();
+            return instance.borrow().node().render(out);
+        }
+        let spec = self.spec.as_ref().expect("Widget is mounted already");
+        let runtime = Runtime::new();
+        runtime.enter(|| spec.render(&runtime.widget_id(&self.name), out))
+    }
+
+    /// The root dom node of the instance. It is looked up every time,
+    /// as the instance may have replaced it rendering on its own.
+    pub(crate) fn dom_node(&self) -> Option<NodeId> {
+        let instance = Runtime::current().instance(self.id.as_ref()?)?;
+        let instance = instance.borrow();
+        instance.node().dom_node()
+    }
+
+    /// Takes over the instance of `from` when it is the same widget,
+    /// passing it the new props and listing the patches of the render
+    /// they cause. Returns whether it did.
+    pub(crate) fn take_over(&mut self, from: &mut Widget, patches: &mut Vec<Patch>) -> bool {
+        if self.name != from.name {
+            return false;
+        }
+        // unwrapping is safe because the old widget has been mounted
+        let id = from.id.as_ref().unwrap();
+        let instance = Runtime::current().instance(id).unwrap();
+        let spec = self.spec.take().expect("Widget is mounted already");
+        let result = spec.update(id, &mut *instance.borrow_mut(), patches);
+        match result {
+            Ok(()) => {
+                self.id = from.id.take();
+                true
+            }
+            Err(spec) => {
+                self.spec = Some(spec);
+                false
+            }
+        }
+    }
+}
+
+impl<P, S, M, N> AnySpec for Spec<P, S, M, N>
+where
+    P: PartialEq + 'static,
+    S: 'static,
+    M: 'static,
+    N: Into<Node<M>> + 'static,
+{
+    fn set_hook(&mut self, name: &str, lifecycle: Lifecycle, hook: &dyn Any) {
+        let hook = match hook.downcast_ref::<Hook<M>>() {
+            Some(hook) => *hook,
+            None => panic!(
+                "The {:?} hook of widget {} returns actions of another message type",
+                lifecycle, name
+            ),
+        };
+        match lifecycle {
+            Lifecycle::Mount => self.hooks.on_mount = Some(hook),
+            Lifecycle::Update => self.hooks.on_update = Some(hook),
+            Lifecycle::Unmount => self.hooks.on_unmount = Some(hook),
+        }
+    }
+
+    fn set_subscriptions(&mut self, name: &str, subscriptions: &dyn Any) {
+        match subscriptions.downcast_ref::<fn(&S, &P) -> Sub<M>>() {
+            Some(subscriptions) => self.subscriptions = Some(*subscriptions),
+            None => panic!(
+                "The subscriptions of widget {} don't match its state, props or message type",
+                name
+            ),
+        }
+    }
+
+    fn instantiate(self: Box<Self>, id: &str) -> Rc<RefCell<dyn AnyInstance>> {
+        let state = (self.state)();
+        let node = Runtime::current().view::<M, _>(id, || (self.view)(&state, &self.props).into());
+        Rc::new(RefCell::new(Instance {
+            props: self.props,
+            state,
+            node,
+            update: self.update,
+            view: self.view,
+            hooks: self.hooks,
+            subscriptions: self.subscriptions,
+            active: Vec::new(),
+        }))
+    }
+
+    #[cfg(feature = "ssr")]
+    fn render(&self, id: &str, out: &mut String) {
+        let state = (self.state)();
+        let node: Node<M> =
+            Runtime::current().view::<M, _>(id, || (self.view)(&state, &self.props).into());
+        node.render(out)
+    }
+
+    fn update(
+        self: Box<Self>,
+        id: &str,
+        instance: &mut dyn AnyInstance,
+        patches: &mut Vec<Patch>,
+    ) -> Result<(), Box<dyn AnySpec>> {
+        let instance = match instance.as_any_mut().downcast_mut::<Instance<P, S, M, N>>() {
+            Some(instance) => instance,
+            None => return Err(self),
+        };
+        instance.hooks = self.hooks;
+        instance.subscriptions = self.subscriptions;
+        // diff nodes if props are different
+        if instance.props != self.props {
+            instance.props = self.props;
+            instance.render(id, patches);
+        }
+        instance.subscribe(id);
+        Ok(())
+    }
+}
+
+impl<P, S, M, N> Instance<P, S, M, N>
+where
+    P: 'static,
+    S: 'static,
+    M: 'static,
+    N: Into<Node<M>> + 'static,
+{
+    fn render(&mut self, id: &str, patches: &mut Vec<Patch>) {
+        let runtime = Runtime::current();
+        let mut new_node =
+            runtime.view::<M, _>(id, || (self.view)(&self.state, &self.props).into());
+        diff::diff_node(id, &mut self.node, &mut new_node, patches);
+        self.node = new_node;
+        runtime.rendered(id);
+        self.after_commit(id, Lifecycle::Update);
+    }
+
+    /// Starts and stops subscriptions to match the current state and props.
+    fn subscribe(&mut self, id: &str) {
+        let subs = match self.subscriptions {
+            Some(subscriptions) => subscriptions(&self.state, &self.props),
+            None => Sub::none(),
+        };
+        sub::update(id, &mut self.active, subs);
+    }
+
+    fn act(&mut self, id: &str, action: Action<M>) {
+        let runtime = Runtime::current();
+        let mut effects = Effects::default();
+        action.effects(&runtime, id, &mut effects);
+        for key in effects.cancel.iter() {
+            runtime.cancel_tasks(id, Some(key));
+        }
+        if effects.render {
+            runtime.schedule_render(id);
+        }
+        for (key, task) in effects.tasks {
+            runtime.spawn(id, key, task);
+        }
+    }
+
+    /// Queues the hook for `lifecycle`, if any, to run with the root
+    /// node the instance has once the dom is committed.
+    fn after_commit(&self, id: &str, lifecycle: Lifecycle) {
+        let hook = match lifecycle {
+            Lifecycle::Mount => self.hooks.on_mount,
+            Lifecycle::Update => self.hooks.on_update,
+            Lifecycle::Unmount => self.hooks.on_unmount,
+        };
+        let Some(hook) = hook else { return };
+
+        let runtime = Runtime::current();
+        let id = id.to_string();
+        runtime.clone().after_commit(Box::new(move || {
+            // it may have been unmounted right away
+            let Some(instance) = runtime.instance(&id) else {
+                return;
+            };
+            let mut instance = instance.borrow_mut();
+            let instance = instance.as_any_mut().downcast_mut::<Self>().unwrap();
+            if let Some(dom_node) = instance.node.dom_node() {
+                let action = hook(WidgetRoot::new(dom_node));
+                instance.act(&id, action);
+            }
+        }));
+    }
+}
+
+impl<P, S, M, N> AnyInstance for Instance<P, S, M, N>
+where
+    P: 'static,
+    S: 'static,
+    M: 'static,
+    N: Into<Node<M>> + 'static,
+{
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn node(&self) -> &dyn AnyNode {
+        &self.node
+    }
+
+    fn node_mut(&mut self) -> &mut dyn AnyNode {
+        &mut self.node
+    }
+
+    fn draw(&mut self, id: &str, patches: &mut Vec<Patch>) {
+        draw::node(id, &mut self.node, patches);
+        self.mounted(id);
+    }
+
+    fn mounted(&mut self, id: &str) {
+        self.subscribe(id);
+        self.after_commit(id, Lifecycle::Mount);
+    }
+
+    fn render(&mut self, id: &str, patches: &mut Vec<Patch>) {
+        Instance::render(self, id, patches)
+    }
+
+    fn update(&mut self, id: &str, msg: Box<dyn Any>) {
+        let msg = *msg.downcast::<M>().unwrap();
+        let action = (self.update)(&mut self.state, &self.props, msg);
+        self.subscribe(id);
+        self.act(id, action);
+    }
+
+    fn unmount(&mut self) -> Option<Box<dyn FnOnce()>> {
+        sub::stop(self.active.drain(..));
+        let hook = self.hooks.on_unmount?;
+        // the dom node is released along with the instance, so it is
+        // looked up right away
+        let root = WidgetRoot::new(self.node.dom_node()?);
+        Some(Box::new(move || {
+            hook(root);
+        }))
+    }
 }
@@ -1,122 +1,176 @@
+//! patch.rs describes the changes to the real dom that drawing and
+//! diffing come up with, and applies them through the current backend.
+
+use std::{cell::RefCell, collections::HashMap};
+
 use crate::{
+    backend::{self, next_id, Handler, ListenerOptions, NodeId},
     console_log,
-    events::{add_event_listener, Listener},
+    events::{self, Listener},
+    runtime::Runtime,
 };
 
-pub fn replace_node(dom_node: &web_sys::Node, by: &web_sys::Node) {
-    console_log!(
-        "[patch] replace node {}: {} by {}: {}",
-        dom_node.node_name(),
-        dom_node.node_type(),
-        by.node_name(),
-        by.node_name()
-    );
-    match dom_node.parent_node() {
-        Some(parent) => match parent.replace_child(by, dom_node) {
-            Ok(_replaced_node) => (),
-            Err(_) => todo!(),
-        },
-        None => todo!(),
-    }
+/// One change to the real dom. Patches only hold plain data, so that
+/// they can be compared, logged, batched and, with the `serde` feature,
+/// serialized before they are applied. The listeners they set wait in a
+/// table on the side, as the functions making their messages only mean
+/// something to the runtime that made them.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Patch {
+    CreateElement {
+        id: NodeId,
+        tag: &'static str,
+    },
+    CreateText {
+        id: NodeId,
+        data: String,
+    },
+    Replace {
+        old: NodeId,
+        new: NodeId,
+    },
+    SetAttribute {
+        id: NodeId,
+        name: &'static str,
+        val: String,
+    },
+    RemoveAttribute {
+        id: NodeId,
+        name: &'static str,
+    },
+    SetText {
+        id: NodeId,
+        data: String,
+    },
+    /// Inserts `child` before `before`, or at the end, moving it when it
+    /// is already attached.
+    Insert {
+        parent: NodeId,
+        child: NodeId,
+        before: Option<NodeId>,
+    },
+    Remove {
+        id: NodeId,
+    },
+    /// Forgets a node that was removed or replaced for good.
+    Release {
+        id: NodeId,
+    },
+    /// Tears down a widget instance that left the view.
+    Unmount {
+        widget: String,
+    },
+    SetListener {
+        id: NodeId,
+        event: &'static str,
+        options: ListenerOptions,
+        listener: ListenerId,
+    },
+    RemoveListener {
+        id: NodeId,
+        event: &'static str,
+    },
 }
 
-pub fn set_attribute(dom_element: &web_sys::Element, name: &str, val: &str) {
-    console_log!(
-        "[patch] set attribute {} -> {} on {}",
-        name,
-        val,
-        dom_element.tag_name()
-    );
-    match dom_element.set_attribute(name, val) {
-        Ok(()) => (),
-        Err(_) => todo!(),
-    }
-}
+/// Refers to a listener waiting in the table on the side until its
+/// `SetListener` is applied. Dropping a patch that is never applied
+/// drops its listener as well.
+#[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ListenerId(pub(crate) u32);
 
-pub fn remove_attribute(dom_element: &web_sys::Element, name: &str) {
-    console_log!(
-        "[patch] remove attribute {} from {}",
-        name,
-        dom_element.tag_name()
-    );
-    match dom_element.remove_attribute(name) {
-        Ok(()) => (),
-        Err(_) => todo!(),
-    }
-}
+/// Makes the handler of a listener, sending to the widget it belongs to.
+type Bind = Box<dyn FnOnce() -> Handler>;
 
-pub fn set_listener<M>(
-    wname: &str,
-    dom_element: &web_sys::Element,
-    name: &'static str,
-    listener: &mut Listener<M>,
-) where
-    M: 'static,
-{
-    console_log!(
-        "[patch] added listener {} to {}",
-        name,
-        dom_element.tag_name()
-    );
-    add_event_listener(wname, name, listener, dom_element)
+thread_local! {
+    static LISTENERS: RefCell<HashMap<u32, Bind>> = RefCell::new(HashMap::new());
 }
 
-pub fn remove_listener<M>(dom_element: &web_sys::Element, name: &str, listener: &Listener<M>) {
-    console_log!(
-        "[patch] removed listener {} from {}",
-        name,
-        dom_element.tag_name()
-    );
-    match dom_element.remove_event_listener_with_callback(name, listener.callback.as_ref().unwrap())
-    {
-        Ok(()) => (),
-        Err(_) => todo!(),
+impl ListenerId {
+    /// Puts `listener` in the table, to send its messages to the widget
+    /// instance `wid` once it is set.
+    pub(crate) fn new<M: 'static>(wid: &str, listener: &Listener<M>) -> Self {
+        let id = next_id().0;
+        let wid = wid.to_string();
+        let listener = listener.clone();
+        let bind: Bind = Box::new(move || events::handler(&wid, &listener));
+        LISTENERS.with(|listeners| listeners.borrow_mut().insert(id, bind));
+        ListenerId(id)
     }
-}
 
-pub fn add_children<'a>(
-    dom_element: &web_sys::Element,
-    children: impl Iterator<Item = &'a web_sys::Node>,
-) {
-    let mut count = 0;
-    for child in children {
-        count += 1;
-        match dom_element.append_child(child) {
-            Ok(_appended_child) => (),
-            Err(_) => todo!(),
-        }
-    }
-    if count > 0 {
-        console_log!(
-            "[patch] add {} children to {}",
-            count,
-            dom_element.tag_name()
-        );
+    /// Takes the listener out of the table, as a handler.
+    fn bind(self) -> Handler {
+        let bind = LISTENERS.with(|listeners| listeners.borrow_mut().remove(&self.0));
+        // unwrapping is safe because only applying a patch takes it out
+        bind.unwrap()()
     }
 }
 
-pub fn remove_children(dom_element: &web_sys::Element, n: usize) {
-    if n > 0 {
-        console_log!(
-            "[patch] remove {} children from {}",
-            n,
-            dom_element.tag_name()
-        );
+impl Drop for ListenerId {
+    fn drop(&mut self) {
+        // dropped outside of the borrow, as dropping a listener may drop
+        // patches in turn
+        let bind = LISTENERS.try_with(|listeners| listeners.borrow_mut().remove(&self.0));
+        drop(bind);
     }
+}
 
-    for _ in 0..n {
-        match dom_element.last_element_child() {
-            Some(last_child) => last_child.remove(),
-            None => todo!(),
+/// Applies `patches` in order. Backends that queue their changes, like
+/// `Batched`, only make them once flushed, which the runtime does once
+/// per frame.
+pub fn apply(patches: Vec<Patch>) {
+    let backend = backend::current();
+    for patch in patches {
+        match patch {
+            Patch::CreateElement { id, tag } => backend.create_element(id, tag),
+            Patch::CreateText { id, data } => backend.create_text(id, &data),
+            Patch::Replace { old, new } => {
+                console_log!("[patch] replace node {:?} by {:?}", old, new);
+                backend.replace_child(old, new)
+            }
+            Patch::SetAttribute { id, name, val } => {
+                console_log!("[patch] set attribute {} -> {} on {:?}", name, val, id);
+                backend.set_attribute(id, name, &val)
+            }
+            Patch::RemoveAttribute { id, name } => {
+                console_log!("[patch] remove attribute {} from {:?}", name, id);
+                backend.remove_attribute(id, name)
+            }
+            Patch::SetText { id, data } => {
+                console_log!("[patch] set text data of {:?} to {}", id, data);
+                backend.set_text(id, &data)
+            }
+            Patch::Insert {
+                parent,
+                child,
+                before,
+            } => {
+                console_log!("[patch] insert {:?} into {:?}", child, parent);
+                backend.insert_child(parent, child, before)
+            }
+            Patch::Remove { id } => {
+                console_log!("[patch] remove node {:?}", id);
+                backend.remove_child(id)
+            }
+            Patch::Release { id } => backend.release(id),
+            Patch::Unmount { widget } => {
+                console_log!("[patch] unmount widget {}", widget);
+                Runtime::current().unmount(&widget)
+            }
+            Patch::SetListener {
+                id,
+                event,
+                options,
+                listener,
+            } => {
+                console_log!("[patch] added listener {} to {:?}", event, id);
+                backend.add_listener(id, event, options, listener.bind())
+            }
+            Patch::RemoveListener { id, event } => {
+                console_log!("[patch] removed listener {} from {:?}", event, id);
+                backend.remove_listener(id, event)
+            }
         }
     }
 }
-
-pub fn set_text_data(dom_text: &web_sys::Text, data: &str) {
-    console_log!(
-        "[patch] set text data from {} to {}",
-        dom_text.node_value().unwrap(),
-        data
-    );
-    dom_text.set_data(data);
-}
@@ -0,0 +1,656 @@
+//! runtime.rs owns what widgets keep between renders: their state, props,
+//! virtual dom and update function. Every app gets its own runtime, so
+//! several apps can live on one page.
+
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::poll_fn,
+    rc::{Rc, Weak},
+    task::{Poll, Waker},
+};
+
+use wasm_rs_async_executor::single_threaded::spawn;
+
+use crate::{
+    action::Task,
+    backend::{self, CallbackId},
+    console_log,
+    nodes::node::AnyNode,
+    patch::{self, Patch},
+};
+
+/// A widget instance with its types erased, as the runtime keeps them.
+pub(crate) trait AnyInstance {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn node(&self) -> &dyn AnyNode;
+    fn node_mut(&mut self) -> &mut dyn AnyNode;
+    /// Runs the update function with `msg`, which has to be the widget's
+    /// message type.
+    fn update(&mut self, name: &str, msg: Box<dyn Any>);
+    /// Lists the patches that draw the virtual dom the instance was made
+    /// with, and calls it mounted.
+    fn draw(&mut self, name: &str, patches: &mut Vec<Patch>);
+    /// Starts the subscriptions and queues the `on_mount` hook, once the
+    /// virtual dom is drawn or has adopted existing dom nodes.
+    fn mounted(&mut self, name: &str);
+    /// Runs the view again, listing the patches to the new virtual dom.
+    fn render(&mut self, name: &str, patches: &mut Vec<Patch>);
+    /// Stops the subscriptions, and returns the `on_unmount` hook ready
+    /// to run once the instance is gone.
+    fn unmount(&mut self) -> Option<Box<dyn FnOnce()>>;
+}
+
+/// A handle to the runtime of an app, as returned by `run`.
+#[derive(Clone, Default)]
+pub struct Runtime {
+    inner: Rc<Inner>,
+}
+
+/// A runtime that isn't kept alive by those holding it, like the
+/// callbacks its widgets pass down in their props.
+#[derive(Clone)]
+pub(crate) struct WeakRuntime {
+    inner: Weak<Inner>,
+}
+
+impl WeakRuntime {
+    pub(crate) fn upgrade(&self) -> Option<Runtime> {
+        self.inner.upgrade().map(|inner| Runtime { inner })
+    }
+
+    pub(crate) fn ptr_eq(&self, other: &WeakRuntime) -> bool {
+        self.inner.ptr_eq(&other.inner)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    instances: RefCell<HashMap<String, Rc<RefCell<dyn AnyInstance>>>>,
+    /// Numbers the instances, to tell widgets with the same name apart.
+    mounted: Cell<u32>,
+    /// Messages wait here while another message is being handled.
+    pending: RefCell<VecDeque<(String, Box<dyn Any>)>>,
+    updating: Cell<bool>,
+    /// Lifecycle hooks, waiting for the dom to be committed.
+    hooks: RefCell<VecDeque<Box<dyn FnOnce()>>>,
+    /// The instances to render on the next frame, by mount order.
+    dirty: RefCell<BTreeMap<u32, String>>,
+    frame: Cell<Option<CallbackId>>,
+    /// The tasks every widget instance runs.
+    tasks: RefCell<HashMap<String, Vec<Rc<Running>>>>,
+    /// The instances whose views are running, with their message types.
+    viewing: RefCell<Vec<(String, TypeId)>>,
+}
+
+/// A spawned task, which can be aborted from outside.
+#[derive(Default)]
+struct Running {
+    key: Option<String>,
+    task: RefCell<Option<Task>>,
+    aborted: Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+}
+
+impl Running {
+    fn done(&self) -> bool {
+        self.aborted.get() || matches!(self.task.try_borrow().as_deref(), Ok(None))
+    }
+
+    fn abort(&self) {
+        self.aborted.set(true);
+        // a task aborting itself while it is polled is dropped once
+        // the poll returns
+        if let Ok(mut task) = self.task.try_borrow_mut() {
+            *task = None;
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Vec<Runtime>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The runtime widgets are being created and updated in.
+    pub(crate) fn current() -> Runtime {
+        CURRENT.with(|current| {
+            current
+                .borrow()
+                .last()
+                .cloned()
+                .expect("Widgets can only be used inside of a running app")
+        })
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakRuntime {
+        WeakRuntime {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+
+    /// Makes this the current runtime while running `f`.
+    pub(crate) fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        CURRENT.with(|current| current.borrow_mut().push(self.clone()));
+        let result = f();
+        CURRENT.with(|current| current.borrow_mut().pop());
+        result
+    }
+
+    /// A fresh id for an instance of the widget named `name`.
+    pub(crate) fn widget_id(&self, name: &str) -> String {
+        let n = self.inner.mounted.get();
+        self.inner.mounted.set(n + 1);
+        format!("{}#{}", name, n)
+    }
+
+    pub(crate) fn instance(&self, name: &str) -> Option<Rc<RefCell<dyn AnyInstance>>> {
+        self.inner.instances.borrow().get(name).cloned()
+    }
+
+    pub(crate) fn insert_instance(&self, name: &str, instance: Rc<RefCell<dyn AnyInstance>>) {
+        self.inner
+            .instances
+            .borrow_mut()
+            .insert(name.to_string(), instance);
+    }
+
+    /// Runs the view of the widget instance `id`, which has messages of
+    /// type `M`, so that callbacks made in it send to that instance.
+    pub(crate) fn view<M: 'static, T>(&self, id: &str, f: impl FnOnce() -> T) -> T {
+        let viewing = (id.to_string(), TypeId::of::<M>());
+        self.inner.viewing.borrow_mut().push(viewing);
+        let result = f();
+        self.inner.viewing.borrow_mut().pop();
+        result
+    }
+
+    /// The widget instance whose view is running, and its message type.
+    pub(crate) fn viewing(&self) -> Option<(String, TypeId)> {
+        self.inner.viewing.borrow().last().cloned()
+    }
+
+    /// Drops the instance `id`, and tears down what it has drawn.
+    pub(crate) fn unmount(&self, id: &str) {
+        let instance = self.inner.instances.borrow_mut().remove(id);
+        if let Some(instance) = instance {
+            let mut instance = instance.borrow_mut();
+            if let Some(hook) = instance.unmount() {
+                self.after_commit(hook);
+            }
+            self.cancel_tasks(id, None);
+            self.rendered(id);
+            instance.node().release();
+        }
+    }
+
+    /// Spawns `task` for the widget instance `id`, under `key` so that it
+    /// can be aborted along with the others under it.
+    pub(crate) fn spawn(&self, id: &str, key: Option<String>, task: Task) {
+        let running = Rc::new(Running {
+            key,
+            task: RefCell::new(Some(task)),
+            ..Default::default()
+        });
+        {
+            let mut tasks = self.inner.tasks.borrow_mut();
+            let tasks = tasks.entry(id.to_string()).or_default();
+            tasks.retain(|running| !running.done());
+            tasks.push(running.clone());
+        }
+
+        spawn(poll_fn(move |cx| {
+            let mut task = running.task.borrow_mut();
+            let poll = match task.as_mut() {
+                Some(_) if running.aborted.get() => Poll::Ready(()),
+                Some(inner) => inner.as_mut().poll(cx),
+                None => Poll::Ready(()),
+            };
+            if poll.is_ready() || running.aborted.get() {
+                *task = None;
+                return Poll::Ready(());
+            }
+            *running.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        }));
+    }
+
+    /// Aborts the tasks of the widget instance `id` running under `key`,
+    /// or all of them.
+    pub(crate) fn cancel_tasks(&self, id: &str, key: Option<&str>) {
+        let aborted: Vec<Rc<Running>> = {
+            let mut tasks = self.inner.tasks.borrow_mut();
+            match key {
+                None => tasks.remove(id).unwrap_or_default(),
+                Some(key) => {
+                    let Some(tasks) = tasks.get_mut(id) else {
+                        return;
+                    };
+                    let (aborted, kept) = tasks
+                        .drain(..)
+                        .partition(|running| running.key.as_deref() == Some(key));
+                    *tasks = kept;
+                    aborted
+                }
+            }
+        };
+        // dropping tasks runs their destructors, so nothing stays borrowed
+        for running in aborted {
+            running.abort();
+        }
+    }
+
+    /// Runs `hook` once the changes being made are in the dom.
+    pub(crate) fn after_commit(&self, hook: Box<dyn FnOnce()>) {
+        self.inner.hooks.borrow_mut().push_back(hook);
+    }
+
+    /// Runs the hooks waiting for the dom, and those they cause in turn.
+    pub(crate) fn commit(&self) {
+        loop {
+            let next = self.inner.hooks.borrow_mut().pop_front();
+            match next {
+                Some(hook) => hook(),
+                None => break,
+            }
+        }
+    }
+
+    /// Marks the widget instance `id` to be rendered again on the next
+    /// animation frame, along with the others that change until then.
+    pub(crate) fn schedule_render(&self, id: &str) {
+        self.inner
+            .dirty
+            .borrow_mut()
+            .insert(mount_order(id), id.to_string());
+        if self.inner.frame.get().is_none() {
+            let runtime = self.clone();
+            let frame = backend::current().request_animation_frame(Rc::new(move |_| {
+                runtime.inner.frame.set(None);
+                runtime.flush();
+            }));
+            self.inner.frame.set(Some(frame));
+        }
+    }
+
+    /// Called when the instance `id` renders, for whatever reason.
+    pub(crate) fn rendered(&self, id: &str) {
+        self.inner.dirty.borrow_mut().remove(&mount_order(id));
+    }
+
+    /// Renders the widgets that changed right away, instead of on the next
+    /// animation frame, like tests do before looking at the dom. Does
+    /// nothing while a message is being handled.
+    pub fn flush(&self) {
+        self.work(|| {
+            if let Some(frame) = self.inner.frame.take() {
+                backend::current().cancel(frame);
+            }
+            // parents first, as they may render their children as well
+            loop {
+                let next = self.inner.dirty.borrow_mut().pop_first();
+                let Some((_, id)) = next else { break };
+                if let Some(instance) = self.instance(&id) {
+                    let mut patches = Vec::new();
+                    instance.borrow_mut().render(&id, &mut patches);
+                    // applied right away, as they may unmount widgets
+                    // that are dirty as well
+                    patch::apply(patches);
+                }
+            }
+            // all changes of the frame at once, before hooks look at them
+            backend::current().flush();
+        })
+    }
+
+    /// Sends `msg` to the widget instance `id`. Messages sent while
+    /// another one is handled, like from within an update, are queued
+    /// and handled right after it.
+    pub(crate) fn update_widget<M: 'static>(&self, id: &str, msg: M) {
+        self.inner
+            .pending
+            .borrow_mut()
+            .push_back((id.to_string(), Box::new(msg)));
+        self.work(|| {});
+    }
+
+    /// Runs `f`, and then the messages queued in the meantime, unless
+    /// the runtime is busy already. Then the queued messages are left
+    /// to the work that is running.
+    fn work(&self, f: impl FnOnce()) {
+        if self.inner.updating.replace(true) {
+            return;
+        }
+        self.enter(|| {
+            f();
+            self.commit();
+            loop {
+                let next = self.inner.pending.borrow_mut().pop_front();
+                let Some((id, msg)) = next else { break };
+                match self.instance(&id) {
+                    Some(instance) => instance.borrow_mut().update(&id, msg),
+                    // like the result of a task that outlived its widget
+                    None => {
+                        console_log!("[runtime] dropped a message to unmounted widget {}", id)
+                    }
+                }
+                self.commit();
+            }
+        });
+        self.inner.updating.set(false);
+    }
+}
+
+/// Instance ids end in the number they were mounted as, so parents sort
+/// before their children.
+fn mount_order(id: &str) -> u32 {
+    id.rsplit('#')
+        .next()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use crate::api::*;
+
+    thread_local! {
+        /// How often the view of `counted` ran.
+        static VIEWS: Cell<u32> = const { Cell::new(0) };
+        /// What `outer`, `inner` and `hooked` did, in order.
+        static LOG: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn toggle() -> Widget {
+        Widget::new(
+            "toggle",
+            (),
+            || false,
+            |on: &mut bool, _: &(), _: ()| {
+                *on = !*on;
+                Action::Diff
+            },
+            |on: &bool, _: &()| -> Html<()> {
+                match on {
+                    true => h1().on("click", || ()),
+                    false => p().on("click", || ()),
+                }
+            },
+        )
+    }
+
+    fn counter() -> Widget {
+        Widget::new(
+            "counter",
+            (),
+            || 0,
+            |count: &mut u32, _: &(), _: ()| {
+                *count += 1;
+                Action::Diff
+            },
+            |count: &u32, _: &()| -> Html<()> { p().on("click", || ()).child(count.to_string()) },
+        )
+    }
+
+    /// Shows the child `child` makes, until double clicked.
+    fn parent(child: fn() -> Widget) -> Widget {
+        Widget::new(
+            "parent",
+            child,
+            || true,
+            |shown: &mut bool, _: &fn() -> Widget, _: ()| {
+                *shown = false;
+                Action::Diff
+            },
+            |shown: &bool, child: &fn() -> Widget| -> Html<()> {
+                let el = div().on("dblclick", || ());
+                match shown {
+                    true => el.child(child()),
+                    false => el,
+                }
+            },
+        )
+    }
+
+    #[test]
+    fn removes_a_child_that_replaced_its_root() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| parent(toggle));
+        let root = memory.root();
+        let el = memory.children(root)[0];
+
+        memory.dispatch(memory.children(el)[0], "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(root), "<body><div><h1></h1></div></body>");
+
+        memory.dispatch(el, "dblclick", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(root), "<body><div></div></body>");
+        assert_eq!(memory.node_count(), 2);
+    }
+
+    #[test]
+    fn updates_and_unmounts_widgets() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| parent(counter));
+        let root = memory.root();
+        let el = memory.children(root)[0];
+        let count = memory.children(el)[0];
+
+        memory.dispatch(count, "click", MemoryEvent::default());
+        memory.dispatch(count, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(root), "<body><div><p>2</p></div></body>");
+        assert_eq!(memory.children(el), vec![count]);
+
+        assert!(runtime.instance("counter#1").is_some());
+        memory.dispatch(el, "dblclick", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(root), "<body><div></div></body>");
+        assert!(runtime.instance("counter#1").is_none());
+        assert_eq!(memory.node_count(), 2);
+    }
+
+    fn counted() -> Widget {
+        Widget::new(
+            "counted",
+            (),
+            || 0,
+            |count: &mut u32, _: &(), _: ()| {
+                *count += 1;
+                Action::Diff
+            },
+            |count: &u32, _: &()| -> Html<()> {
+                VIEWS.with(|views| views.set(views.get() + 1));
+                p().on("click", || ()).child(count.to_string())
+            },
+        )
+    }
+
+    #[test]
+    fn renders_once_for_several_messages() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(counted);
+        let el = memory.children(memory.root())[0];
+        assert_eq!(VIEWS.with(Cell::get), 1);
+
+        for _ in 0..3 {
+            memory.dispatch(el, "click", MemoryEvent::default());
+        }
+        assert_eq!(memory.html(el), "<p>0</p>");
+        memory.animation_frame();
+        assert_eq!(memory.html(el), "<p>3</p>");
+        assert_eq!(VIEWS.with(Cell::get), 2);
+
+        // flushing renders right away, and leaves nothing for the frame
+        memory.dispatch(el, "click", MemoryEvent::default());
+        runtime.flush();
+        memory.animation_frame();
+        assert_eq!(memory.html(el), "<p>4</p>");
+        assert_eq!(VIEWS.with(Cell::get), 3);
+    }
+
+    fn log(entry: impl ToString) {
+        LOG.with(|log| log.borrow_mut().push(entry.to_string()))
+    }
+
+    fn outer() -> Widget {
+        Widget::new(
+            "outer",
+            (),
+            || (),
+            |_: &mut (), _: &(), n: u32| {
+                log(format!("outer got {}", n));
+                Action::Nothing
+            },
+            |_: &(), _: &()| -> Html<u32> { div().child(inner(Callback::new(|n| n))) },
+        )
+    }
+
+    /// Sends two numbers to `outer` while it handles a click.
+    fn inner(send: Callback<u32>) -> Widget {
+        Widget::new(
+            "inner",
+            send,
+            || (),
+            |_: &mut (), send: &Callback<u32>, _: ()| {
+                log("inner started");
+                send.emit(1);
+                send.emit(2);
+                log("inner done");
+                Action::Nothing
+            },
+            |_: &(), _: &Callback<u32>| -> Html<()> { button().on("click", || ()) },
+        )
+    }
+
+    /// A counter that logs its lifecycle, and renders once more as
+    /// soon as it is mounted.
+    fn hooked() -> Widget {
+        Widget::new(
+            "hooked",
+            (),
+            || 0,
+            |count: &mut u32, _: &(), _: ()| {
+                *count += 1;
+                Action::Diff
+            },
+            |count: &u32, _: &()| -> Html<()> { p().on("click", || ()).child(count.to_string()) },
+        )
+        .on_mount(|root| -> Action<()> {
+            log(format!("mounted {:?} {}", root.id, root.web_node.is_some()));
+            Action::Diff
+        })
+        .on_update(|root| -> Action<()> {
+            log(format!("updated {:?}", root.id));
+            Action::Nothing
+        })
+        .on_unmount(|root| -> Action<()> {
+            log(format!("unmounted {:?}", root.id));
+            Action::Nothing
+        })
+    }
+
+    #[test]
+    fn runs_hooks_with_the_root_node_id() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(|| parent(hooked));
+        let el = memory.children(memory.root())[0];
+        let count = memory.children(el)[0];
+        assert_eq!(
+            LOG.with(|log| log.take()),
+            [format!("mounted {:?} false", count)]
+        );
+
+        // the action of the mount hook renders the widget again
+        runtime.flush();
+        assert_eq!(LOG.with(|log| log.take()), [format!("updated {:?}", count)]);
+
+        memory.dispatch(count, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(el), "<div><p>1</p></div>");
+        assert_eq!(LOG.with(|log| log.take()), [format!("updated {:?}", count)]);
+
+        memory.dispatch(el, "dblclick", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(
+            LOG.with(|log| log.take()),
+            [format!("unmounted {:?}", count)]
+        );
+    }
+
+    /// A view made for messages of its own, counting in steps.
+    fn steps() -> Html<u8> {
+        button()
+            .on("click", || 1)
+            .child(p().on("click", || 2).once())
+    }
+
+    /// Adds up the steps clicked, times ten.
+    fn stepped() -> Widget {
+        Widget::new(
+            "stepped",
+            (),
+            || 0,
+            |total: &mut u32, _: &(), step: u32| {
+                *total += step;
+                Action::Diff
+            },
+            |total: &u32, _: &()| -> Html<u32> {
+                div()
+                    .child(total.to_string())
+                    .child(steps().node().map(|step| u32::from(step) * 10))
+            },
+        )
+    }
+
+    #[test]
+    fn maps_the_messages_of_a_view() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(stepped);
+        let el = memory.children(memory.root())[0];
+        let button = memory.children(el)[1];
+        let step = memory.children(button)[0];
+
+        memory.dispatch(step, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(memory.children(el)[0]), "30");
+
+        // the listener of the step went away after one click
+        memory.dispatch(step, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(memory.children(el)[0]), "40");
+    }
+
+    #[test]
+    fn queues_messages_sent_while_updating() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let _runtime = run(outer);
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch(memory.children(el)[0], "click", MemoryEvent::default());
+        assert_eq!(
+            LOG.with(|log| log.take()),
+            ["inner started", "inner done", "outer got 1", "outer got 2"]
+        );
+    }
+}
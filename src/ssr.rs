@@ -0,0 +1,100 @@
+//! ssr.rs renders a virtual dom to an html string, without a backend,
+//! so that pages can be pre-rendered on the server.
+
+use std::fmt::Write;
+
+use crate::nodes::{element::Element, node::Node, widget::Widget};
+
+/// Elements that can't have children, and so don't get a closing tag.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+pub fn render_to_string<M>(node: &Node<M>) -> String {
+    let mut out = String::new();
+    render_node(node, &mut out);
+    out
+}
+
+/// Renders the widget `app` creates. Its views run without being drawn,
+/// so nothing is mounted, subscribed to or left behind in the backend.
+pub fn render_widget_to_string(app: impl FnOnce() -> Widget) -> String {
+    let mut out = String::new();
+    app().render(&mut out);
+    out
+}
+
+fn render_element<M>(element: &Element<M>, out: &mut String) {
+    let _ = write!(out, "<{}", element.tag);
+
+    // sorted, so that the same tree always renders the same markup
+    let mut attrs: Vec<_> = element.attrs.iter().collect();
+    attrs.sort();
+    for (name, val) in attrs {
+        let _ = write!(out, " {}=\"", name);
+        escape(val, true, out);
+        out.push('"');
+    }
+    out.push('>');
+
+    if VOID_ELEMENTS.contains(&element.tag) {
+        return;
+    }
+
+    let mut previous_was_text = false;
+    for child in element.children.iter() {
+        let is_text = matches!(child, Node::Text(_));
+
+        // keeps adjacent texts apart, the browser would merge them otherwise
+        if previous_was_text && is_text {
+            out.push_str("<!---->");
+        }
+        render_node(child, out);
+        previous_was_text = is_text;
+    }
+
+    let _ = write!(out, "</{}>", element.tag);
+}
+
+pub(crate) fn render_node<M>(node: &Node<M>, out: &mut String) {
+    match node {
+        Node::Element(el) => render_element(el, out),
+        Node::Text(txt) => escape(&txt.val, false, out),
+        Node::Widget(widget) => widget.render(out),
+    }
+}
+
+fn escape(val: &str, in_attribute: bool, out: &mut String) {
+    for c in val.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if in_attribute => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::*;
+
+    fn counter() -> Widget {
+        Widget::new(
+            "counter",
+            (),
+            || 3,
+            |_: &mut i32, _: &(), _: ()| Action::Nothing,
+            |count: &i32, _: &()| -> Html<()> { p().child(count.to_string()) },
+        )
+    }
+
+    #[test]
+    fn renders_widgets_without_mounting_them() {
+        let node: Node<()> = div().child(counter()).child("&").node();
+        assert_eq!(render_to_string(&node), "<div><p>3</p>&amp;</div>");
+        assert_eq!(render_widget_to_string(counter), "<p>3</p>");
+    }
+}
@@ -0,0 +1,302 @@
+//! sub.rs describes what a widget keeps listening to for as long as it
+//! exists: timers, animation frames and events outside of the tree. The
+//! runtime asks for the subscriptions again after every update, and only
+//! starts and stops the ones that changed.
+
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    backend::{self, CallbackId, Target},
+    events::{self, Listener, ListenerKind, Timing},
+    runtime::Runtime,
+};
+
+pub struct Sub<M: 'static> {
+    subs: Vec<Subscription<M>>,
+}
+
+enum Subscription<M: 'static> {
+    Every {
+        ms: u32,
+        msg: fn() -> M,
+    },
+    AnimationFrame(fn(f64) -> M),
+    Global {
+        target: Target,
+        event: &'static str,
+        listener: Listener<M>,
+    },
+}
+
+impl<M> Sub<M> {
+    pub fn none() -> Self {
+        Self { subs: Vec::new() }
+    }
+
+    pub fn batch(subs: impl IntoIterator<Item = Sub<M>>) -> Self {
+        Self {
+            subs: subs.into_iter().flat_map(|sub| sub.subs).collect(),
+        }
+    }
+
+    /// Sends `msg()` every `ms` milliseconds.
+    pub fn every(ms: u32, msg: fn() -> M) -> Self {
+        Self::one(Subscription::Every { ms, msg })
+    }
+
+    /// Sends a message before every repaint, made from its timestamp in
+    /// milliseconds.
+    pub fn animation_frame(msg: fn(f64) -> M) -> Self {
+        Self::one(Subscription::AnimationFrame(msg))
+    }
+
+    pub fn window(event: &'static str, action: fn(web_sys::Event) -> M) -> Self {
+        Self::listener_kind(Target::Window, event, action.into())
+    }
+
+    pub fn document(event: &'static str, action: fn(web_sys::Event) -> M) -> Self {
+        Self::listener_kind(Target::Document, event, action.into())
+    }
+
+    /// Like `window`, but only sends the messages `action` makes, like
+    /// for keyboard shortcuts.
+    pub fn window_maybe(event: &'static str, action: fn(web_sys::Event) -> Option<M>) -> Self {
+        Self::listener_kind(Target::Window, event, action.into())
+    }
+
+    /// Like `document`, but only sends the messages `action` makes, like
+    /// for clicks outside of a dropdown.
+    pub fn document_maybe(event: &'static str, action: fn(web_sys::Event) -> Option<M>) -> Self {
+        Self::listener_kind(Target::Document, event, action.into())
+    }
+
+    pub fn listener_kind(target: Target, event: &'static str, action: ListenerKind<M>) -> Self {
+        Self::one(Subscription::Global {
+            target,
+            event,
+            listener: Listener::new(action),
+        })
+    }
+
+    // Like the listener modifiers of elements, these apply to the window
+    // or document listener added last.
+
+    /// Sends the last message once the events have stopped for `ms`
+    /// milliseconds.
+    pub fn debounce(self, ms: u32) -> Self {
+        self.modify_listener(|listener| listener.timing = Some(Timing::Debounce(ms)))
+    }
+
+    /// Sends at most one message every `ms` milliseconds, like for `resize`.
+    pub fn throttle(self, ms: u32) -> Self {
+        self.modify_listener(|listener| listener.timing = Some(Timing::Throttle(ms)))
+    }
+
+    pub fn capture(self) -> Self {
+        self.modify_listener(|listener| listener.options.capture = true)
+    }
+
+    pub fn passive(self) -> Self {
+        self.modify_listener(|listener| listener.options.passive = true)
+    }
+
+    /// Only sends a message for the first event, until the subscription
+    /// is dropped and made again.
+    pub fn once(self) -> Self {
+        self.modify_listener(|listener| listener.options.once = true)
+    }
+
+    pub fn prevent_default(self) -> Self {
+        self.modify_listener(|listener| listener.options.prevent_default = true)
+    }
+
+    pub fn stop_propagation(self) -> Self {
+        self.modify_listener(|listener| listener.options.stop_propagation = true)
+    }
+
+    fn modify_listener(mut self, f: impl FnOnce(&mut Listener<M>)) -> Self {
+        match self.subs.last_mut() {
+            Some(Subscription::Global { listener, .. }) => f(listener),
+            _ => panic!("Listener modifiers apply to the window or document listener added last"),
+        }
+        self
+    }
+
+    fn one(sub: Subscription<M>) -> Self {
+        Self { subs: vec![sub] }
+    }
+}
+
+// subscriptions are the same when they call the same functions
+impl<M> PartialEq for Subscription<M> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Every { ms: ms1, msg: msg1 }, Self::Every { ms: ms2, msg: msg2 }) => {
+                ms1 == ms2 && std::ptr::fn_addr_eq(*msg1, *msg2)
+            }
+            (Self::AnimationFrame(msg1), Self::AnimationFrame(msg2)) => {
+                std::ptr::fn_addr_eq(*msg1, *msg2)
+            }
+            (
+                Self::Global {
+                    target: target1,
+                    event: event1,
+                    listener: listener1,
+                },
+                Self::Global {
+                    target: target2,
+                    event: event2,
+                    listener: listener2,
+                },
+            ) => target1 == target2 && event1 == event2 && listener1 == listener2,
+            (_, _) => false,
+        }
+    }
+}
+
+/// A subscription that has been started, with the callback that stops
+/// it. Animation frames get a new callback every frame.
+pub(crate) struct Active<M: 'static> {
+    sub: Subscription<M>,
+    callback: Rc<Cell<Option<CallbackId>>>,
+}
+
+/// Starts the subscriptions in `subs` that aren't active yet, and stops
+/// the active ones that are no longer in it.
+pub(crate) fn update<M: 'static>(wid: &str, active: &mut Vec<Active<M>>, subs: Sub<M>) {
+    let mut old: Vec<Option<Active<M>>> = active.drain(..).map(Some).collect();
+    for sub in subs.subs {
+        let kept = old
+            .iter_mut()
+            .find(|a| a.as_ref().is_some_and(|a| a.sub == sub))
+            .and_then(Option::take);
+        active.push(match kept {
+            Some(kept) => kept,
+            None => start(wid, sub),
+        });
+    }
+    stop(old.into_iter().flatten());
+}
+
+pub(crate) fn stop<M: 'static>(active: impl IntoIterator<Item = Active<M>>) {
+    let backend = backend::current();
+    for active in active {
+        if let Some(callback) = active.callback.get() {
+            backend.cancel(callback)
+        }
+    }
+}
+
+fn start<M: 'static>(wid: &str, sub: Subscription<M>) -> Active<M> {
+    let backend = backend::current();
+    let runtime = Runtime::current();
+    let callback = Rc::new(Cell::new(None));
+    match &sub {
+        Subscription::Every { ms, msg } => {
+            let (wid, msg) = (wid.to_string(), *msg);
+            let id = backend.set_interval(*ms, Rc::new(move || runtime.update_widget(&wid, msg())));
+            callback.set(Some(id));
+        }
+        Subscription::AnimationFrame(msg) => {
+            request_frame(runtime, wid.to_string(), *msg, callback.clone())
+        }
+        Subscription::Global {
+            target,
+            event,
+            listener,
+        } => {
+            let handler = events::handler(wid, listener);
+            let id = backend.add_global_listener(*target, event, listener.options, handler);
+            callback.set(Some(id));
+        }
+    }
+    Active { sub, callback }
+}
+
+fn request_frame<M: 'static>(
+    runtime: Runtime,
+    wid: String,
+    msg: fn(f64) -> M,
+    callback: Rc<Cell<Option<CallbackId>>>,
+) {
+    let next = callback.clone();
+    let id = backend::current().request_animation_frame(Rc::new(move |timestamp| {
+        // the next frame is requested first, so that the update can still stop it
+        request_frame(runtime.clone(), wid.clone(), msg, next.clone());
+        runtime.update_widget(&wid, msg(timestamp));
+    }));
+    callback.set(Some(id));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::api::*;
+
+    fn parent() -> Widget {
+        Widget::new(
+            "parent",
+            (),
+            || true,
+            |shown: &mut bool, _: &(), _: ()| {
+                *shown = false;
+                Action::Diff
+            },
+            |shown: &bool, _: &()| -> Html<()> {
+                let el = div().on("click", || ());
+                match shown {
+                    true => el.child(child()),
+                    false => el,
+                }
+            },
+        )
+    }
+
+    /// Shows which of its subscriptions got an event.
+    fn child() -> Widget {
+        Widget::new(
+            "child",
+            (),
+            Vec::new,
+            |got: &mut Vec<&'static str>, _: &(), msg: &'static str| {
+                got.push(msg);
+                Action::Diff
+            },
+            |got: &Vec<&'static str>, _: &()| -> Html<&'static str> { p().child(got.join(",")) },
+        )
+        .subscriptions(|_: &Vec<&'static str>, _: &()| {
+            Sub::batch([
+                listen(Target::Document, "keydown", || "document").stop_propagation(),
+                listen(Target::Window, "keydown", || "window"),
+                listen(Target::Window, "resize", || "resize").once(),
+            ])
+        })
+    }
+
+    fn listen(target: Target, event: &'static str, msg: fn() -> &'static str) -> Sub<&'static str> {
+        Sub::listener_kind(target, event, ListenerKind::Factory(msg))
+    }
+
+    #[test]
+    fn listens_outside_of_the_tree_while_mounted() {
+        let memory = Rc::new(Memory::new());
+        set_backend(memory.clone());
+        let runtime = run(parent);
+        let el = memory.children(memory.root())[0];
+
+        memory.dispatch_global(Target::Document, "keydown", MemoryEvent::default());
+        memory.dispatch_global(Target::Window, "keydown", MemoryEvent::default());
+        memory.dispatch_global(Target::Window, "resize", MemoryEvent::default());
+        memory.dispatch_global(Target::Window, "resize", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(el), "<div><p>document,window,resize</p></div>");
+        assert!(!memory.has_global_listener(Target::Window, "resize"));
+
+        memory.dispatch(el, "click", MemoryEvent::default());
+        runtime.flush();
+        assert_eq!(memory.html(el), "<div></div>");
+        assert!(!memory.has_global_listener(Target::Document, "keydown"));
+        assert!(!memory.has_global_listener(Target::Window, "keydown"));
+    }
+}
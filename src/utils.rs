@@ -4,6 +4,7 @@ pub fn document() -> web_sys::Document {
     web_sys::window().unwrap().document().unwrap()
 }
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     // Use `js_namespace` here to bind `console.log(..)` instead of just
@@ -22,6 +23,11 @@ extern "C" {
     fn log_many(a: &str, b: &str);
 }
 
+// There is no console outside of the browser, and the native builds,
+// like servers rendering pages, shouldn't write to their stdout.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn log(_s: &str) {}
+
 #[wasm_bindgen(module = "/src/utils.js")]
 extern "C" {
     fn test() -> String;